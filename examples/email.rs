@@ -1,7 +1,7 @@
 #![allow(unused)]
 use chrono::{DateTime, NaiveDate, NaiveDateTime, NaiveTime};
-use microsoft::model::{Attachment, Page};
-use microsoft::{MicrosoftAuth, MicrosoftClient};
+use microsoft_mail::model::{Attachment, Page};
+use microsoft_mail::{MicrosoftAuth, MicrosoftClient};
 use std::{env, fs};
 
 // what's left?
@@ -12,7 +12,7 @@ use std::{env, fs};
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let access_token = env::var("MICROSOFT_ACCESS_TOKEN").unwrap();
     let refresh_token = env::var("MICROSOFT_REFRESH_TOKEN").unwrap();
-    let auth = MicrosoftAuth::oauth2(access_token, refresh_token, None);
+    let auth = MicrosoftAuth::oauth2(access_token, refresh_token);
     let client = MicrosoftClient::with_auth(auth);
 
     // let dt = NaiveDate::from_ymd_opt(2024, 11, 1).unwrap();
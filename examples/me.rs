@@ -1,11 +1,12 @@
 use std::env::var;
 
-use microsoft::MicrosoftAuth;
+use microsoft_mail::auth::StaticToken;
+use microsoft_mail::MicrosoftAuth;
 
 #[tokio::main]
 async fn main() {
-    let auth = MicrosoftAuth::oauth2(var("BEARER").unwrap(), "".to_string(), None);
-    let client = microsoft::MicrosoftClient::with_auth(auth);
+    let auth = MicrosoftAuth::provider(StaticToken::new(var("BEARER").unwrap()));
+    let client = microsoft_mail::MicrosoftClient::with_auth(auth);
     let me = client.me().await.unwrap();
     dbg!(me);
 }
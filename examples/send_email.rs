@@ -1,15 +1,15 @@
 #![allow(unused)]
 use email::{Body, Email, EmailAddress};
 use file::File;
-use microsoft::model::{Attachment, Page};
-use microsoft::{MicrosoftAuth, MicrosoftClient};
+use microsoft_mail::model::{Attachment, Page};
+use microsoft_mail::{MicrosoftAuth, MicrosoftClient};
 use std::env;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let access_token = env::var("MICROSOFT_ACCESS_TOKEN").unwrap();
     let refresh_token = env::var("MICROSOFT_REFRESH_TOKEN").unwrap();
-    let auth = MicrosoftAuth::oauth2(access_token, refresh_token, None);
+    let auth = MicrosoftAuth::oauth2(access_token, refresh_token);
     let client = MicrosoftClient::with_auth(auth);
 
     // let mut res = client
@@ -0,0 +1,109 @@
+use crate::auth::authorization_code::{auth_error, query_param};
+use crate::auth::Scopes;
+use crate::{Cloud, MicrosoftResult};
+
+/// Consent by a tenant administrator to the permissions configured on the app registration, for every user in
+/// their tenant. Multi-tenant apps need it before app-only access, or delegated access to permissions users
+/// can't consent to themselves, works in a customer's tenant.
+/// see https://learn.microsoft.com/en-us/entra/identity-platform/v2-admin-consent
+///
+/// 1. Send the administrator to the url from [`AdminConsent::url`].
+/// 2. On the redirect back, pass its query string to [`AdminConsent::parse_redirect`].
+#[derive(Debug, Clone)]
+pub struct AdminConsent {
+    endpoint: String,
+    client_id: String,
+    redirect_uri: String,
+    scope: String,
+}
+
+/// What the administrator consented to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AdminConsentResult {
+    /// The tenant id to use for the customer from now on, e.g. with [`crate::MicrosoftAuth::client_credentials`].
+    pub tenant: String,
+    /// The permissions granted, without their resource prefix.
+    pub scopes: Scopes,
+}
+
+impl AdminConsent {
+    /// `redirect_uri` must match one registered on the app. Consent is asked for every Graph permission the
+    /// app registration lists.
+    pub fn new(cloud: Cloud, tenant: &str, client_id: impl Into<String>, redirect_uri: impl Into<String>) -> Self {
+        Self {
+            endpoint: cloud.admin_consent_endpoint(tenant),
+            client_id: client_id.into(),
+            redirect_uri: redirect_uri.into(),
+            scope: format!("{}/.default", cloud.graph_host()),
+        }
+    }
+
+    /// Where to send the administrator. `state` comes back unchanged in the redirect, e.g. to tell which
+    /// customer is onboarding; it should also be unguessable.
+    pub fn url(&self, state: &str) -> String {
+        let query = [
+            ("client_id", self.client_id.as_str()),
+            ("redirect_uri", self.redirect_uri.as_str()),
+            ("scope", self.scope.as_str()),
+            ("state", state),
+        ]
+        .iter()
+        .map(|(k, v)| format!("{k}={}", urlencoding::encode(v)))
+        .collect::<Vec<_>>()
+        .join("&");
+        format!("{}?{query}", self.endpoint)
+    }
+
+    /// Read the redirect back to `redirect_uri`. `callback` is that url, or just its query string.
+    /// Fails with [`crate::MicrosoftError::Auth`] if consent was declined, or the state doesn't match.
+    pub fn parse_redirect(callback: &str, state: &str) -> MicrosoftResult<AdminConsentResult> {
+        if let Some(error) = query_param(callback, "error") {
+            let description = query_param(callback, "error_description").unwrap_or_default();
+            return Err(auth_error(&error, description));
+        }
+        if query_param(callback, "state").as_deref() != Some(state) {
+            return Err(auth_error("invalid_state", "The state in the redirect doesn't match the consent request."));
+        }
+        if !query_param(callback, "admin_consent").is_some_and(|v| v.eq_ignore_ascii_case("true")) {
+            return Err(auth_error("consent_required", "The administrator didn't consent."));
+        }
+        let tenant = query_param(callback, "tenant").ok_or_else(|| auth_error("invalid_request", "The redirect has no tenant."))?;
+        let scope = query_param(callback, "scope").unwrap_or_default();
+        let scopes = scope
+            .split_whitespace()
+            .map(|s| s.rsplit_once('/').map_or(s, |(_, name)| name))
+            .map(Into::into)
+            .collect();
+        Ok(AdminConsentResult { tenant, scopes })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Scope;
+    use crate::MicrosoftError;
+
+    #[test]
+    fn test_admin_consent() {
+        let consent = AdminConsent::new(Cloud::Global, "organizations", "app", "https://example.com/consent");
+        assert_eq!(
+            consent.url("customer-1"),
+            "https://login.microsoftonline.com/organizations/v2.0/adminconsent?client_id=app\
+             &redirect_uri=https%3A%2F%2Fexample.com%2Fconsent&scope=https%3A%2F%2Fgraph.microsoft.com%2F.default&state=customer-1"
+        );
+
+        let callback = "https://example.com/consent?admin_consent=True&tenant=t1\
+                        &scope=https%3A%2F%2Fgraph.microsoft.com%2FMail.Read+https%3A%2F%2Fgraph.microsoft.com%2FMail.Send\
+                        &state=customer-1";
+        let result = AdminConsent::parse_redirect(callback, "customer-1").unwrap();
+        assert_eq!(result.tenant, "t1");
+        assert_eq!(result.scopes, Scopes::from([Scope::MailRead, Scope::MailSend]));
+
+        let err = AdminConsent::parse_redirect(callback, "customer-2").unwrap_err();
+        assert!(matches!(err, MicrosoftError::Auth(e) if e.code == "invalid_state"));
+        let declined = "error=access_denied&error_description=AADSTS65004%3A+declined&state=customer-1";
+        let err = AdminConsent::parse_redirect(declined, "customer-1").unwrap_err();
+        assert!(matches!(err, MicrosoftError::Auth(e) if e.code == "access_denied" && e.message == "AADSTS65004: declined"));
+    }
+}
@@ -0,0 +1,262 @@
+use crate::auth::{TokenStore, ClientCredential, DelegatedAuth, Scopes, TokenResponse};
+use crate::{Cloud, GraphError, MicrosoftAuth, MicrosoftClient, MicrosoftError, MicrosoftResult};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use httpclient::InMemoryResponseExt;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+/// Interactive sign in through the browser, with PKCE.
+/// see https://learn.microsoft.com/en-us/entra/identity-platform/v2-oauth2-auth-code-flow
+///
+/// 1. Redirect the user to the url from [`AuthorizationCodeFlow::authorize_url`], and keep the
+///    [`AuthorizationRequest`] in their session.
+/// 2. On the redirect back, pass its query string to [`AuthorizationCodeFlow::exchange`].
+pub struct AuthorizationCodeFlow {
+    client: httpclient::Client,
+    cloud: Cloud,
+    authorize_endpoint: String,
+    token_endpoint: String,
+    client_id: String,
+    credential: Option<ClientCredential>,
+    redirect_uri: String,
+    scopes: Scopes,
+    store: Option<Arc<dyn TokenStore>>,
+}
+
+impl fmt::Debug for AuthorizationCodeFlow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuthorizationCodeFlow")
+            .field("token_endpoint", &self.token_endpoint)
+            .field("client_id", &self.client_id)
+            .field("scope", &self.scopes)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The secrets of one sign in attempt. Keep it server side (or in an encrypted cookie) until the user comes back.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuthorizationRequest {
+    pub state: String,
+    pub nonce: String,
+    pub code_verifier: String,
+}
+
+impl AuthorizationRequest {
+    fn new() -> Self {
+        Self {
+            state: random_token(16),
+            nonce: random_token(16),
+            code_verifier: random_token(32),
+        }
+    }
+
+    /// The S256 PKCE challenge for [`AuthorizationRequest::code_verifier`].
+    pub fn code_challenge(&self) -> String {
+        URL_SAFE_NO_PAD.encode(Sha256::digest(self.code_verifier.as_bytes()))
+    }
+}
+
+fn random_token(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+pub(super) fn auth_error(code: &str, message: impl Into<String>) -> MicrosoftError {
+    MicrosoftError::Auth(Box::new(GraphError {
+        code: code.to_string(),
+        message: message.into(),
+        ..Default::default()
+    }))
+}
+
+/// Find `key` in a url or query string.
+pub(super) fn query_param(url_or_query: &str, key: &str) -> Option<String> {
+    let query = url_or_query.split_once('?').map_or(url_or_query, |(_, q)| q);
+    query.split('&').find_map(|pair| {
+        let (k, v) = pair.split_once('=')?;
+        if k != key {
+            return None;
+        }
+        urlencoding::decode(&v.replace('+', " ")).ok().map(|v| v.into_owned())
+    })
+}
+
+/// The `nonce` claim of an id token. The token came straight from the token endpoint over TLS, so the
+/// signature isn't checked.
+fn id_token_nonce(id_token: &str) -> Option<String> {
+    let claims = id_token.split('.').nth(1)?;
+    let claims: serde_json::Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(claims).ok()?).ok()?;
+    claims["nonce"].as_str().map(str::to_string)
+}
+
+#[derive(Deserialize)]
+struct CodeResponse {
+    #[serde(flatten)]
+    token: TokenResponse,
+    id_token: Option<String>,
+}
+
+impl AuthorizationCodeFlow {
+    /// `redirect_uri` must match one registered on the app. `scopes` should include [`crate::auth::Scope::OfflineAccess`]
+    /// to get a refresh token.
+    pub fn new(
+        cloud: Cloud, tenant: &str, client_id: impl Into<String>, redirect_uri: impl Into<String>, scopes: impl Into<Scopes>,
+    ) -> Self {
+        Self {
+            client: httpclient::Client::new(),
+            store: None,
+            cloud,
+            authorize_endpoint: cloud.authorize_endpoint(tenant),
+            token_endpoint: cloud.token_endpoint(tenant),
+            client_id: client_id.into(),
+            credential: None,
+            redirect_uri: redirect_uri.into(),
+            scopes: scopes.into(),
+        }
+    }
+
+    /// Required for web apps. Single page and native apps are public clients and have none.
+    pub fn credential(mut self, credential: impl Into<ClientCredential>) -> Self {
+        self.credential = Some(credential.into());
+        self
+    }
+
+    pub fn http_client(mut self, client: httpclient::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    /// Save the tokens there after login, and refresh from there. Tokens are only kept in memory by default.
+    pub fn token_store(mut self, store: Arc<dyn TokenStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    pub fn token_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.token_endpoint = endpoint.into();
+        self
+    }
+
+    /// Where to send the user, and the request to keep until they come back.
+    pub fn authorize_url(&self) -> (String, AuthorizationRequest) {
+        let request = AuthorizationRequest::new();
+        let query = [
+            ("client_id", self.client_id.clone()),
+            ("response_type", "code".to_string()),
+            ("redirect_uri", self.redirect_uri.clone()),
+            ("response_mode", "query".to_string()),
+            ("scope", self.scopes.for_cloud(self.cloud)),
+            ("state", request.state.clone()),
+            ("nonce", request.nonce.clone()),
+            ("code_challenge", request.code_challenge()),
+            ("code_challenge_method", "S256".to_string()),
+        ]
+        .iter()
+        .map(|(k, v)| format!("{k}={}", urlencoding::encode(v)))
+        .collect::<Vec<_>>()
+        .join("&");
+        (format!("{}?{query}", self.authorize_endpoint), request)
+    }
+
+    /// Redeem the code from the redirect back to `redirect_uri`. `callback` is that url, or just its query string.
+    /// Fails with [`MicrosoftError::Auth`] if the user declined, or the state doesn't match `request`.
+    pub async fn exchange(&self, request: &AuthorizationRequest, callback: &str) -> MicrosoftResult<MicrosoftAuth> {
+        if let Some(error) = query_param(callback, "error") {
+            let description = query_param(callback, "error_description").unwrap_or_default();
+            return Err(auth_error(&error, description));
+        }
+        if query_param(callback, "state").as_deref() != Some(request.state.as_str()) {
+            return Err(auth_error("invalid_state", "The state in the redirect doesn't match the authorization request."));
+        }
+        let code = query_param(callback, "code").ok_or_else(|| auth_error("invalid_request", "The redirect has no code."))?;
+        let mut form = BTreeMap::from([
+            ("client_id", self.client_id.clone()),
+            ("grant_type", "authorization_code".to_string()),
+            ("code", code),
+            ("redirect_uri", self.redirect_uri.clone()),
+            ("code_verifier", request.code_verifier.clone()),
+            ("scope", self.scopes.for_cloud(self.cloud)),
+        ]);
+        if let Some(credential) = &self.credential {
+            form.extend(credential.form_params(&self.client_id, &self.token_endpoint));
+        }
+        let res = self.client.post(&self.token_endpoint).form(form).await?;
+        let data: CodeResponse = res.json()?;
+        if let Some(id_token) = &data.id_token {
+            if id_token_nonce(id_token).as_deref() != Some(request.nonce.as_str()) {
+                return Err(auth_error("invalid_nonce", "The id token's nonce doesn't match the authorization request."));
+            }
+        }
+        let mut auth =
+            DelegatedAuth::signed_in(&self.token_endpoint, &self.client_id, data.token.into(), self.store.as_ref()).await?;
+        if let Some(credential) = &self.credential {
+            auth = auth.credential(credential.clone());
+        }
+        Ok(MicrosoftAuth::OAuth2 {
+            middleware: Arc::new(auth),
+        })
+    }
+
+    /// [`AuthorizationCodeFlow::exchange`], then a client for the flow's cloud.
+    pub async fn client(&self, request: &AuthorizationRequest, callback: &str) -> MicrosoftResult<MicrosoftClient> {
+        let auth = self.exchange(request, callback).await?;
+        MicrosoftClient::builder().cloud(self.cloud).auth(auth).build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Scope;
+    use crate::test_util::serve;
+    use http_body_util::BodyExt;
+
+    #[tokio::test]
+    async fn test_authorization_code() {
+        let url = serve(|req| async move {
+            let body = req.into_body().collect().await.unwrap().to_bytes();
+            let body = String::from_utf8(body.to_vec()).unwrap();
+            let verifier = query_param(&body, "code_verifier").unwrap();
+            assert_eq!(query_param(&body, "code").as_deref(), Some("abc"));
+            assert_eq!(query_param(&body, "client_secret").as_deref(), Some("secret"));
+            // echo the verifier back as the nonce, so the test can check both
+            let claims = URL_SAFE_NO_PAD.encode(format!(r#"{{"nonce":"{verifier}"}}"#));
+            (200, format!(r#"{{"access_token":"access","refresh_token":"refresh","expires_in":3599,"id_token":"e30.{claims}.sig"}}"#))
+        })
+        .await;
+
+        let flow = AuthorizationCodeFlow::new(
+            Cloud::Global,
+            "common",
+            "id",
+            "https://example.com/callback",
+            [Scope::MailSend, Scope::OfflineAccess],
+        )
+        .credential("secret")
+        .token_endpoint(format!("{url}/token"));
+        let (authorize_url, mut request) = flow.authorize_url();
+        assert!(authorize_url.starts_with("https://login.microsoftonline.com/common/oauth2/v2.0/authorize?client_id=id"));
+        assert_eq!(query_param(&authorize_url, "scope").as_deref(), Some("offline_access Mail.Send"));
+        assert_eq!(query_param(&authorize_url, "code_challenge").unwrap(), request.code_challenge());
+        request.nonce = request.code_verifier.clone();
+
+        let wrong_state = "https://example.com/callback?code=abc&state=other";
+        let Err(err) = flow.exchange(&request, wrong_state).await else {
+            panic!("expected a state mismatch")
+        };
+        assert_eq!(err.graph_error().unwrap().code, "invalid_state");
+
+        let callback = format!("https://example.com/callback?code=abc&state={}", request.state);
+        let auth = flow.exchange(&request, &callback).await.unwrap();
+        let MicrosoftAuth::OAuth2 { middleware } = auth else {
+            panic!("expected delegated auth")
+        };
+        assert_eq!(middleware.tokens().await.unwrap().refresh_token.as_deref(), Some("refresh"));
+    }
+}
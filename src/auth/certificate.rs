@@ -0,0 +1,175 @@
+use crate::{MicrosoftError, MicrosoftResult};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::signature::{RandomizedSigner, SignatureEncoding};
+use rsa::RsaPrivateKey;
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::fmt;
+use std::path::Path;
+
+/// How long a client assertion is valid. Entra only needs it for the token request it's sent with.
+const ASSERTION_LIFETIME_SECS: i64 = 10 * 60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SigningAlgorithm {
+    #[default]
+    Rs256,
+    Ps256,
+}
+
+impl SigningAlgorithm {
+    fn as_str(&self) -> &'static str {
+        match self {
+            SigningAlgorithm::Rs256 => "RS256",
+            SigningAlgorithm::Ps256 => "PS256",
+        }
+    }
+}
+
+/// A certificate registered on the app, used instead of a client secret. Each token request carries a
+/// JWT signed with the private key.
+/// see https://learn.microsoft.com/en-us/entra/identity-platform/certificate-credentials
+pub struct ClientCertificate {
+    certificate_der: Vec<u8>,
+    private_key: RsaPrivateKey,
+    algorithm: SigningAlgorithm,
+}
+
+impl fmt::Debug for ClientCertificate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientCertificate")
+            .field("thumbprint", &self.thumbprint())
+            .field("algorithm", &self.algorithm)
+            .finish_non_exhaustive()
+    }
+}
+
+fn config_error(e: impl fmt::Display) -> MicrosoftError {
+    MicrosoftError::Config(format!("Invalid client certificate: {e}"))
+}
+
+fn read(path: &Path) -> MicrosoftResult<Vec<u8>> {
+    std::fs::read(path).map_err(|e| config_error(format!("{}: {e}", path.display())))
+}
+
+impl ClientCertificate {
+    /// The private key can be PKCS#8 (`BEGIN PRIVATE KEY`) or PKCS#1 (`BEGIN RSA PRIVATE KEY`).
+    pub fn from_pem(certificate_pem: &str, private_key_pem: &str) -> MicrosoftResult<Self> {
+        let certificate = pem::parse(certificate_pem).map_err(config_error)?;
+        if certificate.tag() != "CERTIFICATE" {
+            return Err(config_error(format!("expected CERTIFICATE, got {}", certificate.tag())));
+        }
+        let private_key = RsaPrivateKey::from_pkcs8_pem(private_key_pem)
+            .or_else(|_| RsaPrivateKey::from_pkcs1_pem(private_key_pem))
+            .map_err(config_error)?;
+        Ok(Self {
+            certificate_der: certificate.into_contents(),
+            private_key,
+            algorithm: SigningAlgorithm::default(),
+        })
+    }
+
+    pub fn from_pem_files(certificate_path: impl AsRef<Path>, private_key_path: impl AsRef<Path>) -> MicrosoftResult<Self> {
+        let certificate = read(certificate_path.as_ref())?;
+        let private_key = read(private_key_path.as_ref())?;
+        Self::from_pem(&String::from_utf8_lossy(&certificate), &String::from_utf8_lossy(&private_key))
+    }
+
+    /// Load the first key and its certificate from a `.pfx`/`.p12` archive.
+    #[cfg(feature = "pkcs12")]
+    pub fn from_pkcs12(der: &[u8], password: &str) -> MicrosoftResult<Self> {
+        use p12_keystore::{KeyStore, Pkcs12ImportPolicy};
+        let store = KeyStore::from_pkcs12(der, password, Pkcs12ImportPolicy::Strict).map_err(config_error)?;
+        let (_, chain) = store.private_key_chain().ok_or_else(|| config_error("no private key in archive"))?;
+        let certificate = chain.certs().first().ok_or_else(|| config_error("no certificate for the private key"))?;
+        let private_key = RsaPrivateKey::from_pkcs8_der(chain.key().as_der()).map_err(config_error)?;
+        Ok(Self {
+            certificate_der: certificate.as_der().to_vec(),
+            private_key,
+            algorithm: SigningAlgorithm::default(),
+        })
+    }
+
+    #[cfg(feature = "pkcs12")]
+    pub fn from_pkcs12_file(path: impl AsRef<Path>, password: &str) -> MicrosoftResult<Self> {
+        Self::from_pkcs12(&read(path.as_ref())?, password)
+    }
+
+    pub fn algorithm(mut self, algorithm: SigningAlgorithm) -> Self {
+        self.algorithm = algorithm;
+        self
+    }
+
+    /// Base64url SHA-256 of the certificate, sent as the `x5t#S256` header so Entra knows which certificate to verify with.
+    pub fn thumbprint(&self) -> String {
+        URL_SAFE_NO_PAD.encode(Sha256::digest(&self.certificate_der))
+    }
+
+    /// A signed JWT identifying `client_id` to the token endpoint `audience`.
+    pub fn assertion(&self, client_id: &str, audience: &str) -> String {
+        let now = chrono::Utc::now().timestamp();
+        let header = json!({
+            "alg": self.algorithm.as_str(),
+            "typ": "JWT",
+            "x5t#S256": self.thumbprint(),
+        });
+        let claims = json!({
+            "aud": audience,
+            "iss": client_id,
+            "sub": client_id,
+            "jti": uuid::Uuid::new_v4().to_string(),
+            "nbf": now,
+            "iat": now,
+            "exp": now + ASSERTION_LIFETIME_SECS,
+        });
+        let message = format!(
+            "{}.{}",
+            URL_SAFE_NO_PAD.encode(header.to_string()),
+            URL_SAFE_NO_PAD.encode(claims.to_string())
+        );
+        let mut rng = rand::thread_rng();
+        let signature = match self.algorithm {
+            SigningAlgorithm::Rs256 => {
+                let key = rsa::pkcs1v15::SigningKey::<Sha256>::new(self.private_key.clone());
+                key.sign_with_rng(&mut rng, message.as_bytes()).to_vec()
+            }
+            SigningAlgorithm::Ps256 => {
+                let key = rsa::pss::BlindedSigningKey::<Sha256>::new(self.private_key.clone());
+                key.sign_with_rng(&mut rng, message.as_bytes()).to_vec()
+            }
+        };
+        format!("{message}.{}", URL_SAFE_NO_PAD.encode(signature))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rsa::signature::Verifier;
+
+    #[test]
+    fn test_assertion() {
+        let private_key = RsaPrivateKey::new(&mut rand::thread_rng(), 1024).unwrap();
+        let public_key = private_key.to_public_key();
+        let certificate = ClientCertificate {
+            certificate_der: vec![1, 2, 3],
+            private_key,
+            algorithm: SigningAlgorithm::Ps256,
+        };
+        let jwt = certificate.assertion("client", "https://login.microsoftonline.com/tenant/oauth2/v2.0/token");
+        let (message, signature) = jwt.rsplit_once('.').unwrap();
+        let (header, claims) = message.split_once('.').unwrap();
+        let header: serde_json::Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(header).unwrap()).unwrap();
+        assert_eq!(header["alg"], "PS256");
+        assert_eq!(header["x5t#S256"], certificate.thumbprint());
+        let claims: serde_json::Value = serde_json::from_slice(&URL_SAFE_NO_PAD.decode(claims).unwrap()).unwrap();
+        assert_eq!(claims["sub"], "client");
+
+        let signature = rsa::pss::Signature::try_from(URL_SAFE_NO_PAD.decode(signature).unwrap().as_slice()).unwrap();
+        let key = rsa::pss::VerifyingKey::<Sha256>::new(public_key);
+        key.verify(message.as_bytes(), &signature).unwrap();
+    }
+}
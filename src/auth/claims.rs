@@ -0,0 +1,213 @@
+use crate::auth::{Scope, Scopes};
+use crate::{MicrosoftClient, MicrosoftError, MicrosoftResult};
+use async_trait::async_trait;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use chrono::{DateTime, Utc};
+use httpclient::{header, InMemoryBody, InMemoryRequest, InMemoryResponseExt, Middleware, Next, ProtocolResult, Response};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// The claims of a Graph access token, decoded without verifying the signature. Only Graph can tell whether
+/// a token is valid; these are for diagnostics and fail-fast checks.
+/// see https://learn.microsoft.com/en-us/entra/identity-platform/access-token-claims-reference
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct TokenClaims {
+    /// The tenant id.
+    pub tid: Option<String>,
+    /// The user's (or for app-only tokens, the service principal's) object id.
+    pub oid: Option<String>,
+    pub upn: Option<String>,
+    pub preferred_username: Option<String>,
+    /// The app the token was issued to.
+    pub appid: Option<String>,
+    /// Delegated permissions, space separated.
+    pub scp: Option<String>,
+    /// Application permissions.
+    #[serde(default)]
+    pub roles: Vec<String>,
+    pub exp: Option<i64>,
+}
+
+impl TokenClaims {
+    /// Fails for tokens that aren't JWTs. Personal Microsoft account tokens are opaque.
+    pub fn decode(access_token: &str) -> MicrosoftResult<Self> {
+        let invalid = || MicrosoftError::Config("the access token is not a JWT, so its claims can't be read".to_string());
+        let claims = access_token.split('.').nth(1).ok_or_else(invalid)?;
+        let claims = URL_SAFE_NO_PAD.decode(claims).map_err(|_| invalid())?;
+        serde_json::from_slice(&claims).map_err(|_| invalid())
+    }
+
+    /// Delegated and application permissions together.
+    pub fn permissions(&self) -> Scopes {
+        let scp = self.scp.as_deref().unwrap_or_default();
+        let mut permissions = Scopes::from(scp);
+        for role in &self.roles {
+            permissions.insert(Scope::from(role.as_str()));
+        }
+        permissions
+    }
+
+    /// A token without delegated permissions was issued to the app itself, e.g. by client credentials.
+    pub fn is_app_only(&self) -> bool {
+        self.scp.is_none()
+    }
+
+    pub fn expires_at(&self) -> Option<DateTime<Utc>> {
+        self.exp.and_then(|exp| DateTime::from_timestamp(exp, 0))
+    }
+
+    /// The first scope of every requirement that none of the granted permissions satisfy. Each requirement is
+    /// granted by any one of its scopes.
+    pub fn missing(&self, required: &[&[Scope]]) -> Vec<Scope> {
+        let permissions = self.permissions();
+        required
+            .iter()
+            .filter(|alternatives| !alternatives.iter().any(|scope| permissions.contains(scope)))
+            .filter_map(|alternatives| alternatives.first().cloned())
+            .collect()
+    }
+
+    fn require(&self, required: &[&[Scope]]) -> MicrosoftResult<()> {
+        let missing = self.missing(required);
+        if !missing.is_empty() {
+            return Err(MicrosoftError::MissingPermissions(missing));
+        }
+        Ok(())
+    }
+}
+
+/// Marks the request [`CaptureToken`] answers. Anything else, like the token requests the auth middleware sends
+/// through the rest of the chain, is passed on.
+const CAPTURE_TOKEN_HEADER: &str = "x-microsoft-mail-capture-token";
+
+/// Ends the middleware chain with the `Authorization` header the auth middleware set, instead of sending the request.
+#[derive(Debug)]
+struct CaptureToken;
+
+#[async_trait]
+impl Middleware for CaptureToken {
+    async fn handle(&self, request: InMemoryRequest, next: Next<'_>) -> ProtocolResult<Response> {
+        if !request.headers().contains_key(CAPTURE_TOKEN_HEADER) {
+            return next.run(request).await;
+        }
+        let token = request
+            .headers()
+            .get(header::AUTHORIZATION)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .unwrap_or_default();
+        Ok(Response::new(InMemoryBody::Text(token.to_string()).into()))
+    }
+}
+
+impl MicrosoftClient {
+    /// The access token requests are sent with, fetched or refreshed first if needed.
+    pub async fn access_token(&self) -> MicrosoftResult<String> {
+        let mut r = self.client.get("/me").header(CAPTURE_TOKEN_HEADER, "1");
+        r.middlewares.insert(0, Arc::new(CaptureToken));
+        r.middlewares.insert(0, self.authentication.middleware());
+        Ok(r.await?.text()?)
+    }
+
+    /// The claims of [`MicrosoftClient::access_token`].
+    pub async fn claims(&self) -> MicrosoftResult<TokenClaims> {
+        TokenClaims::decode(&self.access_token().await?)
+    }
+
+    /// Fails with [`MicrosoftError::MissingPermissions`] unless the access token grants every requirement.
+    /// See [`crate::request::RequiredPermissions`].
+    pub async fn check_permissions(&self, required: &[&[Scope]]) -> MicrosoftResult<()> {
+        self.claims().await?.require(required)
+    }
+
+    /// [`MicrosoftClient::check_permissions`] before sending a request, if enabled with
+    /// [`crate::MicrosoftClientBuilder::preflight`]. Tokens whose claims can't be read are let through.
+    pub(crate) async fn preflight(&self, required: &[&[Scope]]) -> MicrosoftResult<()> {
+        if !self.preflight || required.is_empty() {
+            return Ok(());
+        }
+        match TokenClaims::decode(&self.access_token().await?) {
+            Ok(claims) => claims.require(required),
+            Err(_) => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{ClientCredentials, StaticToken};
+    use crate::request::{READ_MAIL, SEND_MAIL};
+    use crate::test_util::serve;
+    use crate::{Cloud, MicrosoftAuth};
+    use email::{Body, Email};
+
+    fn jwt(claims: &str) -> String {
+        format!("e30.{}.sig", URL_SAFE_NO_PAD.encode(claims))
+    }
+
+    #[tokio::test]
+    async fn test_preflight() {
+        let token = jwt(r#"{"tid":"t","oid":"o","scp":"Mail.Read User.Read","exp":1700000000}"#);
+        let claims = TokenClaims::decode(&token).unwrap();
+        assert_eq!(claims.tid.as_deref(), Some("t"));
+        assert!(!claims.is_app_only());
+        assert_eq!(claims.expires_at().unwrap().timestamp(), 1_700_000_000);
+
+        // nothing is listening on this port, so the test fails if a request is sent
+        let client = MicrosoftClient::builder()
+            .http_client(httpclient::Client::new())
+            .base_url("http://127.0.0.1:9")
+            .auth(MicrosoftAuth::provider(StaticToken::new(token)))
+            .preflight(true)
+            .build()
+            .unwrap();
+        assert_eq!(client.claims().await.unwrap().oid.as_deref(), Some("o"));
+        let email = Email {
+            from: "me@contoso.com".into(),
+            to: vec!["you@contoso.com".into()],
+            cc: vec![],
+            bcc: vec![],
+            subject: "Hi".to_string(),
+            body: Body::Text("Hello".to_string()),
+            attachments: vec![],
+            reply_to_message_id: None,
+            thread_id: None,
+        };
+        let Err(err) = client.send_email(email).await else {
+            panic!("expected the preflight to fail")
+        };
+        assert!(matches!(&err, MicrosoftError::MissingPermissions(_)));
+        assert_eq!(err.to_string(), "Mail.ReadWrite, Mail.Send missing");
+    }
+
+    #[tokio::test]
+    async fn test_claims_client_credentials() {
+        let url = serve(|req| async move {
+            match req.uri().path() {
+                "/token" => {
+                    let token = jwt(r#"{"tid":"t","appid":"app","roles":["Mail.Read"]}"#);
+                    (200, format!(r#"{{"token_type":"Bearer","expires_in":3599,"access_token":"{token}"}}"#))
+                }
+                path => panic!("only the token endpoint should be called, not {path}"),
+            }
+        })
+        .await;
+        let auth = ClientCredentials::new(Cloud::Global, "t", "app", "secret").token_endpoint(format!("{url}/token"));
+        let client = MicrosoftClient::builder()
+            .http_client(httpclient::Client::new())
+            .base_url(&url)
+            .auth(MicrosoftAuth::ClientCredentials {
+                middleware: Arc::new(auth),
+            })
+            .build()
+            .unwrap();
+        let claims = client.claims().await.unwrap();
+        assert!(claims.is_app_only());
+        assert_eq!(claims.tid.as_deref(), Some("t"));
+        client.check_permissions(&[READ_MAIL]).await.unwrap();
+        let err = client.check_permissions(&[SEND_MAIL]).await.unwrap_err();
+        assert!(matches!(err, MicrosoftError::MissingPermissions(missing) if missing == [Scope::MailSend]));
+    }
+}
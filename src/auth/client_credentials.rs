@@ -0,0 +1,178 @@
+use crate::auth::{token_uri, with_bearer, ClientCredential, DEFAULT_REFRESH_MARGIN};
+use crate::Cloud;
+use async_trait::async_trait;
+use httpclient::{InMemoryRequest, Method, Middleware, Next, ProtocolResult, RequestBuilder, Response, StatusCode};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::time::{Duration, Instant};
+use tokio::sync::Mutex;
+
+/// The OAuth2 client credentials grant. Tokens are fetched on first use, cached, and renewed shortly before expiry.
+/// see https://learn.microsoft.com/en-us/entra/identity-platform/v2-oauth2-client-creds-grant-flow
+pub struct ClientCredentials {
+    token_endpoint: String,
+    client_id: String,
+    credential: ClientCredential,
+    scope: String,
+    refresh_margin: Duration,
+    // a tokio mutex, so concurrent requests wait for one token request instead of each sending their own
+    token: Mutex<Option<CachedToken>>,
+}
+
+struct CachedToken {
+    access_token: String,
+    renew_at: Instant,
+}
+
+impl fmt::Debug for ClientCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ClientCredentials")
+            .field("token_endpoint", &self.token_endpoint)
+            .field("client_id", &self.client_id)
+            .field("scope", &self.scope)
+            .field("refresh_margin", &self.refresh_margin)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+impl ClientCredentials {
+    /// `tenant` must be a tenant id or domain. App-only tokens can't be issued for `common`.
+    pub fn new(cloud: Cloud, tenant: &str, client_id: impl Into<String>, credential: impl Into<ClientCredential>) -> Self {
+        Self {
+            token_endpoint: cloud.token_endpoint(tenant),
+            client_id: client_id.into(),
+            credential: credential.into(),
+            scope: format!("{}/.default", cloud.graph_host()),
+            refresh_margin: DEFAULT_REFRESH_MARGIN,
+            token: Mutex::new(None),
+        }
+    }
+
+    /// Defaults to `{graph host}/.default`, i.e. every application permission the app was granted.
+    pub fn scope(mut self, scope: impl Into<String>) -> Self {
+        self.scope = scope.into();
+        self
+    }
+
+    /// Fetch a new token this long before the cached one expires. Defaults to 5 minutes.
+    pub fn refresh_margin(mut self, margin: Duration) -> Self {
+        self.refresh_margin = margin;
+        self
+    }
+
+    pub fn token_endpoint(mut self, token_endpoint: impl Into<String>) -> Self {
+        self.token_endpoint = token_endpoint.into();
+        self
+    }
+
+    /// A valid access token, fetching a new one if needed. `stale` is a token Graph just rejected.
+    /// If the token endpoint fails, its response is returned as the error.
+    async fn access_token(&self, next: Next<'_>, stale: Option<&str>) -> ProtocolResult<Result<String, Response>> {
+        let mut cached = self.token.lock().await;
+        if let Some(token) = cached.as_ref() {
+            let rejected = stale.is_some_and(|s| s == token.access_token);
+            if !rejected && Instant::now() < token.renew_at {
+                return Ok(Ok(token.access_token.clone()));
+            }
+        }
+        let uri = token_uri(&self.token_endpoint)?;
+        let mut form = BTreeMap::from([
+            ("client_id", self.client_id.clone()),
+            ("scope", self.scope.clone()),
+            ("grant_type", "client_credentials".to_string()),
+        ]);
+        form.extend(self.credential.form_params(&self.client_id, &self.token_endpoint));
+        let req = RequestBuilder::new(next.client, Method::POST, uri).form(form);
+        let res = next.run(req.build()).await?;
+        if res.status().is_client_error() || res.status().is_server_error() {
+            return Ok(Err(res));
+        }
+        let (_, body) = res.into_parts();
+        let data: TokenResponse = body.into_memory().await?.json()?;
+        let lifetime = Duration::from_secs(data.expires_in);
+        *cached = Some(CachedToken {
+            access_token: data.access_token.clone(),
+            renew_at: Instant::now() + lifetime.saturating_sub(self.refresh_margin),
+        });
+        Ok(Ok(data.access_token))
+    }
+}
+
+#[async_trait]
+impl Middleware for ClientCredentials {
+    async fn handle(&self, request: InMemoryRequest, next: Next<'_>) -> ProtocolResult<Response> {
+        let token = match self.access_token(next, None).await? {
+            Ok(token) => token,
+            Err(res) => return Ok(res),
+        };
+        let res = next.run(with_bearer(request.clone(), &token)?).await?;
+        if res.status() != StatusCode::UNAUTHORIZED {
+            return Ok(res);
+        }
+        // the token was revoked, or the app's permissions changed. Try once more with a fresh one.
+        let token = match self.access_token(next, Some(&token)).await? {
+            Ok(token) => token,
+            Err(res) => return Ok(res),
+        };
+        next.run(with_bearer(request, &token)?).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::serve;
+    use http_body_util::BodyExt;
+    use httpclient::InMemoryResponseExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_client_credentials() {
+        let token_requests = Arc::new(AtomicUsize::new(0));
+        let counter = token_requests.clone();
+        let url = serve(move |req| {
+            let counter = counter.clone();
+            async move {
+                if req.uri().path() == "/token" {
+                    let body = req.into_body().collect().await.unwrap().to_bytes();
+                    let body = String::from_utf8(body.to_vec()).unwrap();
+                    assert!(body.contains("grant_type=client_credentials"));
+                    assert!(body.contains("scope=https%3A%2F%2Fgraph.microsoft.com%2F.default"));
+                    let n = counter.fetch_add(1, Ordering::SeqCst);
+                    return (200, format!(r#"{{"token_type":"Bearer","expires_in":3599,"access_token":"token{n}"}}"#));
+                }
+                match req.headers().get("authorization").and_then(|v| v.to_str().ok()) {
+                    Some("Bearer token0") => (200, r#"{"id":"me"}"#.to_string()),
+                    _ => (401, String::new()),
+                }
+            }
+        })
+        .await;
+
+        let auth = ClientCredentials::new(Cloud::Global, "contoso.onmicrosoft.com", "id", "secret")
+            .token_endpoint(format!("{url}/token"));
+        let client = httpclient::Client::new().with_middleware(auth);
+        for _ in 0..2 {
+            let res = client.get(format!("{url}/users/a")).await.unwrap();
+            assert_eq!(res.text().unwrap(), r#"{"id":"me"}"#);
+        }
+        assert_eq!(token_requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_token_endpoint() {
+        let auth = ClientCredentials::new(Cloud::Global, "contoso.onmicrosoft.com", "id", "secret").token_endpoint("not a url");
+        let client = httpclient::Client::new().with_middleware(auth);
+        let err = client.get("http://127.0.0.1:1/users/a").await.unwrap_err();
+        let err = crate::MicrosoftError::from(err);
+        assert!(matches!(err, crate::MicrosoftError::Config(message) if message.starts_with("Invalid token endpoint")));
+    }
+}
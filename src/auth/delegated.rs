@@ -0,0 +1,334 @@
+use crate::auth::{token_uri, with_bearer, ClientCredential, MemoryTokenStore, TokenClaims, TokenStore, DEFAULT_REFRESH_MARGIN};
+use crate::{MicrosoftError, MicrosoftResult};
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use httpclient::{
+    InMemoryRequest, Method, Middleware, Next, ProtocolResult, RequestBuilder, Response, StatusCode,
+};
+use httpclient_oauth2::OAuth2Flow;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Mutex;
+
+/// The tokens of a signed in user. A [`TokenStore`] persists them, to skip the login next time.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenSet {
+    pub access_token: String,
+    /// Only issued when the `offline_access` scope was requested.
+    pub refresh_token: Option<String>,
+    pub expires_at: Option<DateTime<Utc>>,
+}
+
+impl TokenSet {
+    /// Tokens from elsewhere, expiring when the access token's `exp` claim says, if it's a JWT.
+    /// An empty `refresh` means there is no refresh token.
+    pub(crate) fn from_pair(access: String, refresh: String) -> Self {
+        let expires_at = TokenClaims::decode(&access).ok().and_then(|c| c.expires_at());
+        Self {
+            access_token: access,
+            refresh_token: Some(refresh).filter(|r| !r.is_empty()),
+            expires_at,
+        }
+    }
+}
+
+/// A successful response from the token endpoint.
+#[derive(Deserialize)]
+pub(crate) struct TokenResponse {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    pub expires_in: Option<i64>,
+}
+
+impl From<TokenResponse> for TokenSet {
+    fn from(res: TokenResponse) -> Self {
+        Self {
+            access_token: res.access_token,
+            refresh_token: res.refresh_token,
+            expires_at: res.expires_in.map(|secs| Utc::now() + chrono::Duration::seconds(secs)),
+        }
+    }
+}
+
+/// Delegated access with the refresh token grant. Unlike [`httpclient_oauth2::OAuth2`], the credential is
+/// optional, so public clients (CLIs, desktop apps) that have no client secret can refresh too.
+/// see https://learn.microsoft.com/en-us/entra/identity-platform/v2-oauth2-auth-code-flow#refresh-the-access-token
+///
+/// Tokens are read from the [`TokenStore`] before each request, so a refresh by another worker is picked up.
+pub struct DelegatedAuth {
+    token_endpoint: String,
+    client_id: String,
+    credential: Option<ClientCredential>,
+    scope: Option<String>,
+    store: Arc<dyn TokenStore>,
+    refresh_margin: Duration,
+    // a tokio mutex, so concurrent requests that need a new token wait for one refresh instead of each sending their own
+    refresh: Mutex<()>,
+    /// The access token whose proactive refresh failed. It's used until it expires rather than refreshing on every request.
+    failed_refresh: std::sync::Mutex<Option<String>>,
+}
+
+impl fmt::Debug for DelegatedAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DelegatedAuth")
+            .field("token_endpoint", &self.token_endpoint)
+            .field("client_id", &self.client_id)
+            .field("scope", &self.scope)
+            .field("refresh_margin", &self.refresh_margin)
+            .finish_non_exhaustive()
+    }
+}
+
+impl DelegatedAuth {
+    /// Keeps `tokens` in a [`MemoryTokenStore`].
+    pub fn new(token_endpoint: impl Into<String>, client_id: impl Into<String>, tokens: TokenSet) -> Self {
+        Self::with_store(token_endpoint, client_id, Arc::new(MemoryTokenStore::new(tokens)))
+    }
+
+    /// Use the tokens in `store`, and save refreshed tokens there.
+    pub fn with_store(token_endpoint: impl Into<String>, client_id: impl Into<String>, store: Arc<dyn TokenStore>) -> Self {
+        Self {
+            token_endpoint: token_endpoint.into(),
+            client_id: client_id.into(),
+            credential: None,
+            scope: None,
+            store,
+            refresh_margin: DEFAULT_REFRESH_MARGIN,
+            refresh: Mutex::new(()),
+            failed_refresh: std::sync::Mutex::new(None),
+        }
+    }
+
+    /// The refresh endpoint and credentials of `flow`. An empty client secret means a public client.
+    pub fn from_flow(flow: &OAuth2Flow, store: Arc<dyn TokenStore>) -> Self {
+        let middleware = Self::with_store(&flow.refresh_endpoint, &flow.client_id, store);
+        match flow.client_secret.as_str() {
+            "" => middleware,
+            secret => middleware.credential(secret),
+        }
+    }
+
+    /// Required for confidential (web) clients. Public clients must not send one.
+    pub fn credential(mut self, credential: impl Into<ClientCredential>) -> Self {
+        self.credential = Some(credential.into());
+        self
+    }
+
+    /// Scopes to request on refresh. By default, the refreshed token has the scopes that were consented to at login.
+    pub fn scope(mut self, scope: impl Into<String>) -> Self {
+        self.scope = Some(scope.into());
+        self
+    }
+
+    /// Refresh this long before the access token expires. Defaults to 5 minutes.
+    pub fn refresh_margin(mut self, margin: Duration) -> Self {
+        self.refresh_margin = margin;
+        self
+    }
+
+    /// After a login, save `tokens` to `store` if there is one.
+    pub(crate) async fn signed_in(
+        token_endpoint: &str, client_id: &str, tokens: TokenSet, store: Option<&Arc<dyn TokenStore>>,
+    ) -> MicrosoftResult<Self> {
+        let Some(store) = store else {
+            return Ok(Self::new(token_endpoint, client_id, tokens));
+        };
+        store.save(&tokens).await?;
+        Ok(Self::with_store(token_endpoint, client_id, store.clone()))
+    }
+
+    pub fn token_endpoint(&self) -> &str {
+        &self.token_endpoint
+    }
+
+    pub fn store(&self) -> &Arc<dyn TokenStore> {
+        &self.store
+    }
+
+    /// The current tokens.
+    pub async fn tokens(&self) -> MicrosoftResult<TokenSet> {
+        self.store
+            .load()
+            .await?
+            .ok_or_else(|| MicrosoftError::Config("the token store is empty; sign in first".to_string()))
+    }
+
+    /// Whether `tokens` should be refreshed before use. `stale` is a token Graph just rejected.
+    fn needs_refresh(&self, tokens: &TokenSet, stale: Option<&str>) -> bool {
+        if tokens.refresh_token.is_none() {
+            return false;
+        }
+        let now = Utc::now();
+        let expiring = tokens.expires_at.is_some_and(|expires_at| now + self.refresh_margin >= expires_at);
+        let backing_off = self.failed_refresh.lock().unwrap().as_deref() == Some(tokens.access_token.as_str())
+            && tokens.expires_at.is_some_and(|expires_at| now < expires_at);
+        (expiring && !backing_off) || stale == Some(tokens.access_token.as_str())
+    }
+
+    /// A valid access token, refreshed if it's about to expire. `stale` is a token Graph just rejected;
+    /// if it's still the current one, refresh. If the token endpoint fails, its response is returned as the error.
+    async fn access_token(&self, next: Next<'_>, stale: Option<&str>) -> ProtocolResult<Result<String, Response>> {
+        let tokens = self.tokens().await.map_err(MicrosoftError::into_protocol_error)?;
+        if !self.needs_refresh(&tokens, stale) {
+            return Ok(Ok(tokens.access_token));
+        }
+        let _refreshing = self.refresh.lock().await;
+        // another request, or another worker, may have refreshed while we waited
+        let tokens = self.tokens().await.map_err(MicrosoftError::into_protocol_error)?;
+        if !self.needs_refresh(&tokens, stale) {
+            return Ok(Ok(tokens.access_token));
+        }
+        let refresh_token = tokens.refresh_token.clone().unwrap_or_default();
+        let uri = token_uri(&self.token_endpoint)?;
+        let mut form = BTreeMap::from([
+            ("client_id", self.client_id.clone()),
+            ("grant_type", "refresh_token".to_string()),
+            ("refresh_token", refresh_token.clone()),
+        ]);
+        if let Some(scope) = &self.scope {
+            form.insert("scope", scope.clone());
+        }
+        if let Some(credential) = &self.credential {
+            form.extend(credential.form_params(&self.client_id, &self.token_endpoint));
+        }
+        let req = RequestBuilder::new(next.client, Method::POST, uri).form(form);
+        let res = next.run(req.build()).await?;
+        if res.status().is_client_error() || res.status().is_server_error() {
+            // invalid_grant is expected if another worker rotated the refresh token in the meantime
+            let latest = self.tokens().await.map_err(MicrosoftError::into_protocol_error)?;
+            if latest.refresh_token != tokens.refresh_token {
+                return Ok(Ok(latest.access_token));
+            }
+            // a proactive refresh can fail while the current token still works
+            let usable = stale.is_none() && tokens.expires_at.is_some_and(|expires_at| Utc::now() < expires_at);
+            if usable {
+                *self.failed_refresh.lock().unwrap() = Some(tokens.access_token.clone());
+                return Ok(Ok(tokens.access_token));
+            }
+            return Ok(Err(res));
+        }
+        let (_, body) = res.into_parts();
+        let data: TokenResponse = body.into_memory().await?.json()?;
+        let mut refreshed = TokenSet::from(data);
+        // the refresh token isn't always rotated
+        refreshed.refresh_token.get_or_insert(refresh_token);
+        let swapped = self.store.compare_and_swap(Some(&tokens), &refreshed).await;
+        if !swapped.map_err(MicrosoftError::into_protocol_error)? {
+            // another worker refreshed first. Use its tokens, so every worker converges on one refresh token.
+            let latest = self.tokens().await.map_err(MicrosoftError::into_protocol_error)?;
+            return Ok(Ok(latest.access_token));
+        }
+        Ok(Ok(refreshed.access_token))
+    }
+}
+
+#[async_trait]
+impl Middleware for DelegatedAuth {
+    async fn handle(&self, request: InMemoryRequest, next: Next<'_>) -> ProtocolResult<Response> {
+        let token = match self.access_token(next, None).await? {
+            Ok(token) => token,
+            Err(res) => return Ok(res),
+        };
+        let res = next.run(with_bearer(request.clone(), &token)?).await?;
+        if res.status() != StatusCode::UNAUTHORIZED {
+            return Ok(res);
+        }
+        let fresh = match self.access_token(next, Some(&token)).await? {
+            Ok(fresh) => fresh,
+            Err(res) => return Ok(res),
+        };
+        if fresh == token {
+            // nothing to refresh with
+            return Ok(res);
+        }
+        next.run(with_bearer(request, &fresh)?).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::serve;
+    use httpclient::InMemoryResponseExt;
+    use std::future::IntoFuture;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_single_flight_refresh() {
+        let refreshes = Arc::new(AtomicUsize::new(0));
+        let counter = refreshes.clone();
+        let url = serve(move |req| {
+            let counter = counter.clone();
+            async move {
+                if req.uri().path() == "/token" {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    tokio::time::sleep(Duration::from_millis(50)).await;
+                    return (200, r#"{"access_token":"fresh","refresh_token":"refresh1","expires_in":3599}"#.to_string());
+                }
+                match req.headers().get("authorization").and_then(|v| v.to_str().ok()) {
+                    Some("Bearer fresh") => (200, "{}".to_string()),
+                    _ => (401, String::new()),
+                }
+            }
+        })
+        .await;
+
+        // still valid, but inside the refresh margin
+        let tokens = TokenSet {
+            access_token: "expiring".to_string(),
+            refresh_token: Some("refresh0".to_string()),
+            expires_at: Some(Utc::now() + chrono::Duration::seconds(60)),
+        };
+        let auth = DelegatedAuth::new(format!("{url}/token"), "id", tokens);
+        let client = httpclient::Client::new().with_middleware(auth);
+        let requests = (0..10).map(|_| client.get(format!("{url}/me")).into_future());
+        for res in futures::future::join_all(requests).await {
+            assert_eq!(res.unwrap().text().unwrap(), "{}");
+        }
+        assert_eq!(refreshes.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_refresh_backs_off() {
+        let refreshes = Arc::new(AtomicUsize::new(0));
+        let counter = refreshes.clone();
+        let url = serve(move |req| {
+            let counter = counter.clone();
+            async move {
+                if req.uri().path() == "/token" {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    return (400, r#"{"error":"temporarily_unavailable"}"#.to_string());
+                }
+                (200, "{}".to_string())
+            }
+        })
+        .await;
+
+        let tokens = TokenSet {
+            access_token: "expiring".to_string(),
+            refresh_token: Some("refresh0".to_string()),
+            expires_at: Some(Utc::now() + chrono::Duration::seconds(60)),
+        };
+        let client = httpclient::Client::new().with_middleware(DelegatedAuth::new(format!("{url}/token"), "id", tokens));
+        for _ in 0..3 {
+            assert_eq!(client.get(format!("{url}/me")).await.unwrap().text().unwrap(), "{}");
+        }
+        assert_eq!(refreshes.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_from_pair() {
+        use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+        use base64::Engine;
+
+        let token = format!("e30.{}.sig", URL_SAFE_NO_PAD.encode(r#"{"exp":1700000000}"#));
+        let tokens = TokenSet::from_pair(token, "refresh".to_string());
+        assert_eq!(tokens.expires_at, DateTime::from_timestamp(1_700_000_000, 0));
+        let tokens = TokenSet::from_pair("opaque".to_string(), String::new());
+        assert_eq!(tokens.expires_at, None);
+        assert_eq!(tokens.refresh_token, None);
+    }
+}
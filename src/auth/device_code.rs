@@ -0,0 +1,198 @@
+use crate::auth::{TokenStore, DelegatedAuth, Scopes, TokenResponse};
+use crate::{Cloud, GraphError, MicrosoftAuth, MicrosoftError, MicrosoftResult};
+use httpclient::InMemoryResponseExt;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+const DEVICE_CODE_GRANT: &str = "urn:ietf:params:oauth:grant-type:device_code";
+
+/// Sign in on another device, for CLIs and headless servers that can't receive a redirect.
+/// see https://learn.microsoft.com/en-us/entra/identity-platform/v2-oauth2-device-code
+///
+/// The app registration must allow public client flows.
+pub struct DeviceCodeFlow {
+    client: httpclient::Client,
+    device_code_endpoint: String,
+    token_endpoint: String,
+    client_id: String,
+    scope: String,
+    store: Option<Arc<dyn TokenStore>>,
+}
+
+impl fmt::Debug for DeviceCodeFlow {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeviceCodeFlow")
+            .field("token_endpoint", &self.token_endpoint)
+            .field("client_id", &self.client_id)
+            .field("scope", &self.scope)
+            .finish_non_exhaustive()
+    }
+}
+
+/// What to show the user. [`DeviceCode::message`] has it all in one sentence.
+#[derive(Debug, Clone, Deserialize)]
+pub struct DeviceCode {
+    pub user_code: String,
+    pub verification_uri: String,
+    pub message: String,
+    /// Seconds until the code expires.
+    pub expires_in: u64,
+    /// Seconds to wait between polls.
+    pub interval: u64,
+    device_code: String,
+}
+
+impl DeviceCodeFlow {
+    /// Include [`crate::auth::Scope::OfflineAccess`] to get a refresh token, or the login only lasts as long as
+    /// the first access token.
+    pub fn new(cloud: Cloud, tenant: &str, client_id: impl Into<String>, scopes: impl Into<Scopes>) -> Self {
+        Self {
+            client: httpclient::Client::new(),
+            store: None,
+            device_code_endpoint: format!("{}/{tenant}/oauth2/v2.0/devicecode", cloud.login_host()),
+            token_endpoint: cloud.token_endpoint(tenant),
+            client_id: client_id.into(),
+            scope: scopes.into().for_cloud(cloud),
+        }
+    }
+
+    pub fn http_client(mut self, client: httpclient::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    pub fn device_code_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.device_code_endpoint = endpoint.into();
+        self
+    }
+
+    /// Save the tokens there after login, and refresh from there. Tokens are only kept in memory by default.
+    pub fn token_store(mut self, store: Arc<dyn TokenStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    pub fn token_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.token_endpoint = endpoint.into();
+        self
+    }
+
+    /// Request a code for the user to enter at [`DeviceCode::verification_uri`].
+    pub async fn start(&self) -> MicrosoftResult<DeviceCode> {
+        let form = BTreeMap::from([("client_id", self.client_id.as_str()), ("scope", self.scope.as_str())]);
+        let res = self.client.post(&self.device_code_endpoint).form(form).await?;
+        Ok(res.json()?)
+    }
+
+    /// Wait until the user has signed in. Fails with [`MicrosoftError::Auth`] if they decline, or the code expires.
+    pub async fn poll(&self, code: &DeviceCode) -> MicrosoftResult<MicrosoftAuth> {
+        let form = BTreeMap::from([
+            ("client_id", self.client_id.as_str()),
+            ("grant_type", DEVICE_CODE_GRANT),
+            ("device_code", code.device_code.as_str()),
+        ]);
+        let expires_at = Instant::now() + Duration::from_secs(code.expires_in);
+        let mut interval = Duration::from_secs(code.interval);
+        loop {
+            tokio::time::sleep(interval).await;
+            let e = match self.client.post(&self.token_endpoint).form(&form).await {
+                Ok(res) => {
+                    let data: TokenResponse = res.json()?;
+                    let auth =
+                        DelegatedAuth::signed_in(&self.token_endpoint, &self.client_id, data.into(), self.store.as_ref()).await?;
+                    return Ok(MicrosoftAuth::OAuth2 {
+                        middleware: Arc::new(auth),
+                    });
+                }
+                Err(e) => MicrosoftError::from(e),
+            };
+            match e.graph_error().map(|e| e.code.as_str()) {
+                Some("authorization_pending") => {}
+                Some("slow_down") => interval += Duration::from_secs(5),
+                _ => return Err(e),
+            }
+            if Instant::now() >= expires_at {
+                return Err(MicrosoftError::Auth(Box::new(GraphError {
+                    code: "expired_token".to_string(),
+                    message: "The device code expired before the user signed in.".to_string(),
+                    ..Default::default()
+                })));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::serve;
+    use http_body_util::BodyExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_device_code() {
+        let polls = Arc::new(AtomicUsize::new(0));
+        let counter = polls.clone();
+        let url = serve(move |req| {
+            let counter = counter.clone();
+            async move {
+                let path = req.uri().path().to_string();
+                let auth = req.headers().get("authorization").and_then(|v| v.to_str().ok()).map(str::to_string);
+                let body = req.into_body().collect().await.unwrap().to_bytes();
+                let body = String::from_utf8(body.to_vec()).unwrap();
+                match path.as_str() {
+                    "/devicecode" => (
+                        200,
+                        r#"{"device_code":"dc","user_code":"ABCD-EFGH","verification_uri":"https://microsoft.com/devicelogin","expires_in":900,"interval":0,"message":"Enter ABCD-EFGH"}"#.to_string(),
+                    ),
+                    "/token" if body.contains("grant_type=refresh_token") => {
+                        assert!(!body.contains("client_secret"));
+                        assert!(body.contains("refresh_token=refresh0"));
+                        (200, r#"{"access_token":"access1","expires_in":3599}"#.to_string())
+                    }
+                    "/token" => {
+                        assert!(body.contains("device_code=dc"));
+                        if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                            return (400, r#"{"error":"authorization_pending","error_description":"AADSTS70016: pending"}"#.to_string());
+                        }
+                        (200, r#"{"access_token":"access0","refresh_token":"refresh0","expires_in":3599}"#.to_string())
+                    }
+                    _ if auth.as_deref() == Some("Bearer access1") => (
+                        200,
+                        r#"{"@odata.context":"x","id":"me","userPrincipalName":"me@contoso.com"}"#.to_string(),
+                    ),
+                    _ => (401, String::new()),
+                }
+            }
+        })
+        .await;
+
+        let flow = DeviceCodeFlow::new(Cloud::Global, "common", "id", "Mail.Read offline_access")
+            .device_code_endpoint(format!("{url}/devicecode"))
+            .token_endpoint(format!("{url}/token"));
+        let code = flow.start().await.unwrap();
+        assert_eq!(code.user_code, "ABCD-EFGH");
+        let auth = flow.poll(&code).await.unwrap();
+        assert_eq!(polls.load(Ordering::SeqCst), 2);
+
+        // the first token is rejected, and refreshed without a client secret
+        let MicrosoftAuth::OAuth2 { middleware } = &auth else {
+            panic!("expected delegated auth")
+        };
+        let middleware = middleware.clone();
+        let client = crate::MicrosoftClient::builder()
+            .http_client(httpclient::Client::new())
+            .base_url(&url)
+            .retry_policy(crate::retry::RetryPolicy::none())
+            .auth(auth)
+            .build()
+            .unwrap();
+        assert_eq!(client.me().await.unwrap().id, "me");
+        let tokens = middleware.tokens().await.unwrap();
+        assert_eq!(tokens.access_token, "access1");
+        assert_eq!(tokens.refresh_token.as_deref(), Some("refresh0"));
+    }
+}
@@ -0,0 +1,145 @@
+//! The ways a [`crate::MicrosoftClient`] can authenticate. Each is a middleware that sets the `Authorization` header.
+mod admin_consent;
+mod authorization_code;
+mod certificate;
+mod claims;
+mod client_credentials;
+mod delegated;
+mod device_code;
+mod on_behalf_of;
+mod provider;
+mod scope;
+mod store;
+
+pub use admin_consent::{AdminConsent, AdminConsentResult};
+pub use authorization_code::{AuthorizationCodeFlow, AuthorizationRequest};
+pub use certificate::{ClientCertificate, SigningAlgorithm};
+pub use claims::TokenClaims;
+pub use client_credentials::ClientCredentials;
+pub use delegated::{DelegatedAuth, TokenSet};
+pub use device_code::{DeviceCode, DeviceCodeFlow};
+pub use on_behalf_of::OnBehalfOf;
+pub use provider::{StaticToken, TokenProvider};
+pub use scope::{Scope, Scopes};
+pub use store::{FileTokenStore, MemoryTokenStore, TokenStore};
+
+pub(crate) use delegated::TokenResponse;
+
+use crate::{shared_oauth2_flow, MicrosoftError};
+use httpclient::{header, InMemoryRequest, Middleware, ProtocolResult, Uri};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Renew tokens this long before they expire, so a request never leaves with a token that expires in flight.
+pub(crate) const DEFAULT_REFRESH_MARGIN: Duration = Duration::from_secs(5 * 60);
+
+/// How a confidential client proves its identity to the token endpoint.
+#[derive(Debug, Clone)]
+pub enum ClientCredential {
+    Secret(String),
+    Certificate(Arc<ClientCertificate>),
+}
+
+impl ClientCredential {
+    /// The form fields to add to a token request sent to `token_endpoint`.
+    pub(crate) fn form_params(&self, client_id: &str, token_endpoint: &str) -> Vec<(&'static str, String)> {
+        match self {
+            ClientCredential::Secret(secret) => vec![("client_secret", secret.clone())],
+            ClientCredential::Certificate(certificate) => vec![
+                (
+                    "client_assertion_type",
+                    "urn:ietf:params:oauth:client-assertion-type:jwt-bearer".to_string(),
+                ),
+                ("client_assertion", certificate.assertion(client_id, token_endpoint)),
+            ],
+        }
+    }
+}
+
+impl From<String> for ClientCredential {
+    fn from(value: String) -> Self {
+        ClientCredential::Secret(value)
+    }
+}
+
+impl From<&str> for ClientCredential {
+    fn from(value: &str) -> Self {
+        ClientCredential::Secret(value.to_string())
+    }
+}
+
+impl From<ClientCertificate> for ClientCredential {
+    fn from(value: ClientCertificate) -> Self {
+        ClientCredential::Certificate(Arc::new(value))
+    }
+}
+
+pub enum MicrosoftAuth {
+    /// Delegated access on behalf of a signed in user, e.g. after an [`AuthorizationCodeFlow`] or
+    /// [`DeviceCodeFlow`] login.
+    OAuth2 { middleware: Arc<DelegatedAuth> },
+    /// App-only access, e.g. for daemons. There is no signed in user, so `/me` doesn't work; address mailboxes
+    /// explicitly instead.
+    ClientCredentials { middleware: Arc<ClientCredentials> },
+    /// Tokens from the caller's own source. See [`StaticToken`] for tests.
+    Provider(Arc<dyn TokenProvider>),
+}
+
+impl MicrosoftAuth {
+    /// Uses the [`shared_oauth2_flow`]. See [`MicrosoftAuth::oauth2_with_flow`] to pass the app registration explicitly.
+    /// Refreshed tokens are only kept in memory; use [`MicrosoftAuth::oauth2_with_store`] to persist them.
+    pub fn oauth2(access: impl Into<String>, refresh: impl Into<String>) -> Self {
+        Self::oauth2_with_flow(shared_oauth2_flow(), access, refresh)
+    }
+
+    /// An empty `refresh` means the access token is used until it expires.
+    pub fn oauth2_with_flow(flow: &httpclient_oauth2::OAuth2Flow, access: impl Into<String>, refresh: impl Into<String>) -> Self {
+        let tokens = TokenSet::from_pair(access.into(), refresh.into());
+        Self::oauth2_with_store(flow, Arc::new(MemoryTokenStore::new(tokens)))
+    }
+
+    /// Use, and refresh, the tokens in `store`.
+    pub fn oauth2_with_store(flow: &httpclient_oauth2::OAuth2Flow, store: Arc<dyn TokenStore>) -> Self {
+        Self::OAuth2 {
+            middleware: Arc::new(DelegatedAuth::from_flow(flow, store)),
+        }
+    }
+
+    /// App-only access in the global cloud. `credential` is the client secret or a [`ClientCertificate`].
+    /// See [`ClientCredentials::new`] for other clouds.
+    pub fn client_credentials(tenant: &str, client_id: impl Into<String>, credential: impl Into<ClientCredential>) -> Self {
+        let middleware = ClientCredentials::new(crate::Cloud::Global, tenant, client_id, credential);
+        Self::ClientCredentials {
+            middleware: Arc::new(middleware),
+        }
+    }
+
+    pub fn provider(provider: impl TokenProvider + 'static) -> Self {
+        Self::Provider(Arc::new(provider))
+    }
+
+    pub(crate) fn middleware(&self) -> Arc<dyn Middleware> {
+        match self {
+            MicrosoftAuth::OAuth2 { middleware } => middleware.clone(),
+            MicrosoftAuth::ClientCredentials { middleware } => middleware.clone(),
+            MicrosoftAuth::Provider(provider) => Arc::new(provider::ProviderMiddleware(provider.clone())),
+        }
+    }
+}
+
+/// A malformed token endpoint fails the request with [`MicrosoftError::Config`], rather than panicking in the middleware.
+fn token_uri(endpoint: &str) -> ProtocolResult<Uri> {
+    endpoint
+        .parse()
+        .map_err(|e| MicrosoftError::Config(format!("Invalid token endpoint {endpoint:?}: {e}")).into_protocol_error())
+}
+
+/// Fails with [`MicrosoftError::Config`] if the token can't be sent in a header, e.g. it ends with a newline.
+fn with_bearer(mut request: InMemoryRequest, token: &str) -> ProtocolResult<InMemoryRequest> {
+    let value = format!("Bearer {token}").parse().map_err(|_| {
+        MicrosoftError::Config("the access token contains characters that aren't allowed in a header".to_string())
+            .into_protocol_error()
+    })?;
+    request.headers_mut().insert(header::AUTHORIZATION, value);
+    Ok(request)
+}
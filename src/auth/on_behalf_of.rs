@@ -0,0 +1,172 @@
+use crate::auth::{ClientCredential, Scopes, TokenProvider, DEFAULT_REFRESH_MARGIN};
+use crate::{Cloud, MicrosoftAuth, MicrosoftResult};
+use async_trait::async_trait;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use httpclient::InMemoryResponseExt;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// The on-behalf-of flow: an API exchanges the access token it was called with for a Graph token for the same user.
+/// see https://learn.microsoft.com/en-us/entra/identity-platform/v2-oauth2-on-behalf-of-flow
+///
+/// Graph tokens are cached per incoming assertion until shortly before they expire.
+pub struct OnBehalfOf {
+    client: httpclient::Client,
+    token_endpoint: String,
+    client_id: String,
+    credential: ClientCredential,
+    scope: String,
+    // keyed on a hash of the assertion. Each entry has its own lock, so one user's exchange doesn't wait on another's.
+    cache: Mutex<HashMap<String, Arc<tokio::sync::Mutex<Option<CachedToken>>>>>,
+}
+
+struct CachedToken {
+    access_token: String,
+    renew_at: Instant,
+}
+
+impl fmt::Debug for OnBehalfOf {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("OnBehalfOf")
+            .field("token_endpoint", &self.token_endpoint)
+            .field("client_id", &self.client_id)
+            .field("scope", &self.scope)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    expires_in: u64,
+}
+
+impl OnBehalfOf {
+    /// `client_id` and `credential` are those of the API receiving the assertion. `scopes` are the Graph
+    /// permissions to request, e.g. `[Scope::MailRead, Scope::MailSend]`.
+    pub fn new(
+        cloud: Cloud, tenant: &str, client_id: impl Into<String>, credential: impl Into<ClientCredential>,
+        scopes: impl Into<Scopes>,
+    ) -> Self {
+        Self {
+            client: httpclient::Client::new(),
+            token_endpoint: cloud.token_endpoint(tenant),
+            client_id: client_id.into(),
+            credential: credential.into(),
+            scope: scopes.into().for_cloud(cloud),
+            cache: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn http_client(mut self, client: httpclient::Client) -> Self {
+        self.client = client;
+        self
+    }
+
+    pub fn token_endpoint(mut self, endpoint: impl Into<String>) -> Self {
+        self.token_endpoint = endpoint.into();
+        self
+    }
+
+    /// Authenticate as the user of `assertion`, the bearer token of the incoming request.
+    pub fn auth(self: &Arc<Self>, assertion: impl Into<String>) -> MicrosoftAuth {
+        MicrosoftAuth::Provider(Arc::new(Assertion {
+            flow: self.clone(),
+            assertion: assertion.into(),
+        }))
+    }
+
+    /// A Graph token for the user of `assertion`. `stale` is a token Graph just rejected.
+    pub async fn token(&self, assertion: &str, stale: Option<&str>) -> MicrosoftResult<String> {
+        let key = URL_SAFE_NO_PAD.encode(Sha256::digest(assertion.as_bytes()));
+        let entry = {
+            let mut cache = self.cache.lock().unwrap();
+            // drop what has expired, so the cache doesn't grow with every user ever seen
+            let now = Instant::now();
+            cache.retain(|_, entry| {
+                let in_use = Arc::strong_count(entry) > 1;
+                in_use || entry.try_lock().is_ok_and(|cached| cached.as_ref().is_some_and(|c| c.renew_at > now))
+            });
+            cache.entry(key).or_default().clone()
+        };
+        let mut cached = entry.lock().await;
+        if let Some(token) = cached.as_ref() {
+            let rejected = stale.is_some_and(|s| s == token.access_token);
+            if !rejected && Instant::now() < token.renew_at {
+                return Ok(token.access_token.clone());
+            }
+        }
+        let mut form = BTreeMap::from([
+            ("client_id", self.client_id.clone()),
+            ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer".to_string()),
+            ("assertion", assertion.to_string()),
+            ("scope", self.scope.clone()),
+            ("requested_token_use", "on_behalf_of".to_string()),
+        ]);
+        form.extend(self.credential.form_params(&self.client_id, &self.token_endpoint));
+        let res = self.client.post(&self.token_endpoint).form(form).await?;
+        let data: TokenResponse = res.json()?;
+        let lifetime = Duration::from_secs(data.expires_in);
+        *cached = Some(CachedToken {
+            access_token: data.access_token.clone(),
+            renew_at: Instant::now() + lifetime.saturating_sub(DEFAULT_REFRESH_MARGIN),
+        });
+        Ok(data.access_token)
+    }
+}
+
+/// One user's assertion, bound to the flow that exchanges it.
+struct Assertion {
+    flow: Arc<OnBehalfOf>,
+    assertion: String,
+}
+
+#[async_trait]
+impl TokenProvider for Assertion {
+    async fn token(&self, stale: Option<&str>) -> MicrosoftResult<String> {
+        self.flow.token(&self.assertion, stale).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Scope;
+    use crate::test_util::serve;
+    use http_body_util::BodyExt;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[tokio::test]
+    async fn test_on_behalf_of() {
+        let exchanges = Arc::new(AtomicUsize::new(0));
+        let counter = exchanges.clone();
+        let url = serve(move |req| {
+            let counter = counter.clone();
+            async move {
+                let body = req.into_body().collect().await.unwrap().to_bytes();
+                let body = String::from_utf8(body.to_vec()).unwrap();
+                assert!(body.contains("requested_token_use=on_behalf_of"));
+                assert!(body.contains("client_secret=secret"));
+                let user = body.split("assertion=").nth(1).unwrap().split('&').next().unwrap().to_string();
+                counter.fetch_add(1, Ordering::SeqCst);
+                (200, format!(r#"{{"access_token":"graph-{user}","expires_in":3599}}"#))
+            }
+        })
+        .await;
+
+        let flow = OnBehalfOf::new(Cloud::Global, "contoso.onmicrosoft.com", "api", "secret", [Scope::MailRead])
+            .token_endpoint(format!("{url}/token"));
+        assert_eq!(flow.token("alice", None).await.unwrap(), "graph-alice");
+        assert_eq!(flow.token("alice", None).await.unwrap(), "graph-alice");
+        assert_eq!(flow.token("bob", None).await.unwrap(), "graph-bob");
+        assert_eq!(exchanges.load(Ordering::SeqCst), 2);
+        // a rejected token is exchanged again
+        flow.token("alice", Some("graph-alice")).await.unwrap();
+        assert_eq!(exchanges.load(Ordering::SeqCst), 3);
+    }
+}
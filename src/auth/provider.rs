@@ -0,0 +1,108 @@
+use crate::auth::with_bearer;
+use crate::{MicrosoftError, MicrosoftResult};
+use async_trait::async_trait;
+use httpclient::{InMemoryRequest, Middleware, Next, ProtocolResult, Response, StatusCode};
+use std::fmt;
+use std::sync::Arc;
+
+/// A source of Graph access tokens the crate doesn't manage itself, e.g. a secret manager or a local
+/// metadata endpoint. The provider is responsible for caching and renewing its tokens.
+#[async_trait]
+pub trait TokenProvider: Send + Sync {
+    /// An access token for Graph. `stale` is a token Graph just rejected, so a cached token can be skipped.
+    async fn token(&self, stale: Option<&str>) -> MicrosoftResult<String>;
+}
+
+/// The same bearer token for every request. Meant for tests and short scripts; nothing is refreshed.
+#[derive(Clone)]
+pub struct StaticToken(String);
+
+impl StaticToken {
+    pub fn new(token: impl Into<String>) -> Self {
+        Self(token.into())
+    }
+}
+
+impl fmt::Debug for StaticToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("StaticToken(..)")
+    }
+}
+
+#[async_trait]
+impl TokenProvider for StaticToken {
+    async fn token(&self, _stale: Option<&str>) -> MicrosoftResult<String> {
+        Ok(self.0.clone())
+    }
+}
+
+/// Sets the `Authorization` header from a [`TokenProvider`].
+pub(crate) struct ProviderMiddleware(pub Arc<dyn TokenProvider>);
+
+impl fmt::Debug for ProviderMiddleware {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProviderMiddleware(..)")
+    }
+}
+
+#[async_trait]
+impl Middleware for ProviderMiddleware {
+    async fn handle(&self, request: InMemoryRequest, next: Next<'_>) -> ProtocolResult<Response> {
+        let token = self.0.token(None).await.map_err(MicrosoftError::into_protocol_error)?;
+        let res = next.run(with_bearer(request.clone(), &token)?).await?;
+        if res.status() != StatusCode::UNAUTHORIZED {
+            return Ok(res);
+        }
+        let fresh = self.0.token(Some(&token)).await.map_err(MicrosoftError::into_protocol_error)?;
+        if fresh == token {
+            return Ok(res);
+        }
+        next.run(with_bearer(request, &fresh)?).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::serve;
+    use crate::{MicrosoftAuth, MicrosoftClient};
+
+    struct Failing;
+
+    #[async_trait]
+    impl TokenProvider for Failing {
+        async fn token(&self, _stale: Option<&str>) -> MicrosoftResult<String> {
+            Err(MicrosoftError::Config("vault unreachable".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_provider() {
+        let url = serve(|req| async move {
+            match req.headers().get("authorization").and_then(|v| v.to_str().ok()) {
+                Some("Bearer test") => (
+                    200,
+                    r#"{"@odata.context":"x","id":"me","userPrincipalName":"me@contoso.com"}"#.to_string(),
+                ),
+                _ => (401, String::new()),
+            }
+        })
+        .await;
+        let client = |auth| {
+            MicrosoftClient::builder()
+                .http_client(httpclient::Client::new())
+                .base_url(&url)
+                .auth(auth)
+                .build()
+                .unwrap()
+        };
+        let me = client(MicrosoftAuth::provider(StaticToken::new("test"))).me().await.unwrap();
+        assert_eq!(me.id, "me");
+        // provider errors come back as they were returned
+        let err = client(MicrosoftAuth::provider(Failing)).me().await.unwrap_err();
+        assert!(matches!(err, MicrosoftError::Config(message) if message == "vault unreachable"));
+        // a token that can't be sent in a header fails the request instead of panicking
+        let err = client(MicrosoftAuth::provider(StaticToken::new("abc\n"))).me().await.unwrap_err();
+        assert!(matches!(err, MicrosoftError::Config(message) if message.contains("access token")));
+    }
+}
@@ -0,0 +1,194 @@
+use crate::Cloud;
+use serde::{Serialize, Serializer};
+use std::collections::BTreeSet;
+use std::convert::Infallible;
+use std::fmt;
+use std::str::FromStr;
+
+/// A delegated permission, or one of the OpenID Connect scopes.
+/// see https://learn.microsoft.com/en-us/graph/permissions-reference
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Scope {
+    OpenId,
+    Profile,
+    Email,
+    /// Needed to get a refresh token.
+    OfflineAccess,
+    UserRead,
+    MailRead,
+    MailReadShared,
+    MailReadWrite,
+    MailReadWriteShared,
+    MailSend,
+    MailSendShared,
+    MailboxSettingsRead,
+    MailboxSettingsReadWrite,
+    /// Any other scope, as it appears in the permissions reference.
+    Other(String),
+}
+
+impl Scope {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Scope::OpenId => "openid",
+            Scope::Profile => "profile",
+            Scope::Email => "email",
+            Scope::OfflineAccess => "offline_access",
+            Scope::UserRead => "User.Read",
+            Scope::MailRead => "Mail.Read",
+            Scope::MailReadShared => "Mail.Read.Shared",
+            Scope::MailReadWrite => "Mail.ReadWrite",
+            Scope::MailReadWriteShared => "Mail.ReadWrite.Shared",
+            Scope::MailSend => "Mail.Send",
+            Scope::MailSendShared => "Mail.Send.Shared",
+            Scope::MailboxSettingsRead => "MailboxSettings.Read",
+            Scope::MailboxSettingsReadWrite => "MailboxSettings.ReadWrite",
+            Scope::Other(s) => s,
+        }
+    }
+
+    /// The OpenID Connect scopes belong to the identity platform rather than to Graph.
+    fn is_openid(&self) -> bool {
+        matches!(self, Scope::OpenId | Scope::Profile | Scope::Email | Scope::OfflineAccess)
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl Serialize for Scope {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.as_str())
+    }
+}
+
+impl FromStr for Scope {
+    type Err = Infallible;
+
+    /// Scope names are case insensitive. Unknown scopes become [`Scope::Other`].
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let known = [
+            Scope::OpenId,
+            Scope::Profile,
+            Scope::Email,
+            Scope::OfflineAccess,
+            Scope::UserRead,
+            Scope::MailRead,
+            Scope::MailReadShared,
+            Scope::MailReadWrite,
+            Scope::MailReadWriteShared,
+            Scope::MailSend,
+            Scope::MailSendShared,
+            Scope::MailboxSettingsRead,
+            Scope::MailboxSettingsReadWrite,
+        ];
+        Ok(known
+            .into_iter()
+            .find(|scope| scope.as_str().eq_ignore_ascii_case(s))
+            .unwrap_or_else(|| Scope::Other(s.to_string())))
+    }
+}
+
+impl From<&str> for Scope {
+    fn from(value: &str) -> Self {
+        Scope::from_str(value).unwrap()
+    }
+}
+
+/// A set of scopes, e.g. `Scopes::from([Scope::MailSend, Scope::OfflineAccess])`.
+/// Displays as the space separated list the identity platform expects.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Scopes(BTreeSet<Scope>);
+
+impl Scopes {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with(mut self, scope: Scope) -> Self {
+        self.0.insert(scope);
+        self
+    }
+
+    pub fn insert(&mut self, scope: Scope) -> bool {
+        self.0.insert(scope)
+    }
+
+    pub fn contains(&self, scope: &Scope) -> bool {
+        self.0.contains(scope)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Scope> {
+        self.0.iter()
+    }
+
+    /// The scope parameter for `cloud`. Graph scopes outside the global cloud must name their resource,
+    /// e.g. `https://graph.microsoft.us/Mail.Read`.
+    pub fn for_cloud(&self, cloud: Cloud) -> String {
+        if cloud == Cloud::Global {
+            return self.to_string();
+        }
+        self.0
+            .iter()
+            .map(|scope| match scope {
+                s if s.is_openid() || s.as_str().contains("://") => s.to_string(),
+                s => format!("{}/{s}", cloud.graph_host()),
+            })
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+impl fmt::Display for Scopes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut first = true;
+        for scope in &self.0 {
+            if !first {
+                f.write_str(" ")?;
+            }
+            first = false;
+            f.write_str(scope.as_str())?;
+        }
+        Ok(())
+    }
+}
+
+/// Parses a space separated list, such as the `scp` claim or a token response's `scope`.
+impl From<&str> for Scopes {
+    fn from(value: &str) -> Self {
+        value.split_whitespace().map(Scope::from).collect()
+    }
+}
+
+impl<const N: usize> From<[Scope; N]> for Scopes {
+    fn from(value: [Scope; N]) -> Self {
+        value.into_iter().collect()
+    }
+}
+
+impl FromIterator<Scope> for Scopes {
+    fn from_iter<T: IntoIterator<Item = Scope>>(iter: T) -> Self {
+        Self(iter.into_iter().collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_scopes() {
+        let scopes = Scopes::from("mail.send offline_access https://graph.microsoft.com/.default");
+        assert!(scopes.contains(&Scope::MailSend));
+        assert_eq!(scopes.to_string(), "offline_access Mail.Send https://graph.microsoft.com/.default");
+        let scopes = Scopes::from([Scope::MailRead, Scope::OfflineAccess]);
+        assert_eq!(scopes.for_cloud(Cloud::UsGovL4), "offline_access https://graph.microsoft.us/Mail.Read");
+    }
+}
@@ -0,0 +1,155 @@
+use crate::auth::TokenSet;
+use crate::{MicrosoftError, MicrosoftResult};
+use async_trait::async_trait;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::path::{Path, PathBuf};
+use tokio::sync::Mutex;
+
+/// Where a user's tokens live between refreshes. Entra rotates the refresh token on every refresh, so workers
+/// sharing one account must share one store, or they invalidate each other's refresh tokens.
+#[async_trait]
+pub trait TokenStore: Send + Sync {
+    async fn load(&self) -> MicrosoftResult<Option<TokenSet>>;
+
+    async fn save(&self, tokens: &TokenSet) -> MicrosoftResult<()>;
+
+    /// Store `new` only if the stored tokens are still `current`. Returns `false` if they changed, e.g. because
+    /// another worker refreshed first.
+    async fn compare_and_swap(&self, current: Option<&TokenSet>, new: &TokenSet) -> MicrosoftResult<bool>;
+}
+
+/// Tokens kept in memory, shared by the clients of one process.
+#[derive(Debug, Default)]
+pub struct MemoryTokenStore {
+    tokens: Mutex<Option<TokenSet>>,
+}
+
+impl MemoryTokenStore {
+    pub fn new(tokens: TokenSet) -> Self {
+        Self {
+            tokens: Mutex::new(Some(tokens)),
+        }
+    }
+}
+
+#[async_trait]
+impl TokenStore for MemoryTokenStore {
+    async fn load(&self) -> MicrosoftResult<Option<TokenSet>> {
+        Ok(self.tokens.lock().await.clone())
+    }
+
+    async fn save(&self, tokens: &TokenSet) -> MicrosoftResult<()> {
+        *self.tokens.lock().await = Some(tokens.clone());
+        Ok(())
+    }
+
+    async fn compare_and_swap(&self, current: Option<&TokenSet>, new: &TokenSet) -> MicrosoftResult<bool> {
+        let mut tokens = self.tokens.lock().await;
+        if tokens.as_ref() != current {
+            return Ok(false);
+        }
+        *tokens = Some(new.clone());
+        Ok(true)
+    }
+}
+
+/// Tokens kept in a JSON file, shared by every process that uses the same path. Writes hold an exclusive
+/// lock on `{path}.lock` and replace the file atomically, so readers never see a partial write.
+#[derive(Debug, Clone)]
+pub struct FileTokenStore {
+    path: PathBuf,
+}
+
+impl FileTokenStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+
+    fn read(path: &Path) -> io::Result<Option<TokenSet>> {
+        match fs::read(path) {
+            Ok(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn write(path: &Path, tokens: &TokenSet) -> io::Result<()> {
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec(tokens)?)?;
+        fs::rename(tmp, path)
+    }
+
+    fn lock(path: &Path) -> io::Result<File> {
+        let mut lock_path = path.as_os_str().to_owned();
+        lock_path.push(".lock");
+        let file = OpenOptions::new().create(true).truncate(false).write(true).open(lock_path)?;
+        file.lock()?;
+        Ok(file)
+    }
+
+    /// Run blocking file io off the async runtime.
+    async fn blocking<T: Send + 'static>(&self, f: impl FnOnce(&Path) -> io::Result<T> + Send + 'static) -> MicrosoftResult<T> {
+        let path = self.path.clone();
+        tokio::task::spawn_blocking(move || f(&path))
+            .await
+            .map_err(|e| MicrosoftError::Config(format!("Token store task failed: {e}")))?
+            .map_err(|e| MicrosoftError::Config(format!("Token store {}: {e}", self.path.display())))
+    }
+}
+
+#[async_trait]
+impl TokenStore for FileTokenStore {
+    async fn load(&self) -> MicrosoftResult<Option<TokenSet>> {
+        self.blocking(Self::read).await
+    }
+
+    async fn save(&self, tokens: &TokenSet) -> MicrosoftResult<()> {
+        let tokens = tokens.clone();
+        self.blocking(move |path| {
+            let _lock = Self::lock(path)?;
+            Self::write(path, &tokens)
+        })
+        .await
+    }
+
+    async fn compare_and_swap(&self, current: Option<&TokenSet>, new: &TokenSet) -> MicrosoftResult<bool> {
+        let current = current.cloned();
+        let new = new.clone();
+        self.blocking(move |path| {
+            let _lock = Self::lock(path)?;
+            if Self::read(path)? != current {
+                return Ok(false);
+            }
+            Self::write(path, &new)?;
+            Ok(true)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tokens(n: u32) -> TokenSet {
+        TokenSet {
+            access_token: format!("access{n}"),
+            refresh_token: Some(format!("refresh{n}")),
+            expires_at: chrono::DateTime::from_timestamp(1_700_000_000 + i64::from(n), 0),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_file_store() {
+        let path = std::env::temp_dir().join(format!("microsoft_mail_tokens_{}.json", uuid::Uuid::new_v4()));
+        let store = FileTokenStore::new(&path);
+        assert_eq!(store.load().await.unwrap(), None);
+        store.save(&tokens(0)).await.unwrap();
+        assert!(store.compare_and_swap(Some(&tokens(0)), &tokens(1)).await.unwrap());
+        // a second worker still holding the old tokens loses
+        assert!(!store.compare_and_swap(Some(&tokens(0)), &tokens(2)).await.unwrap());
+        assert_eq!(store.load().await.unwrap().unwrap().refresh_token.as_deref(), Some("refresh1"));
+        fs::remove_file(&path).unwrap();
+    }
+}
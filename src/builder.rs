@@ -0,0 +1,233 @@
+use crate::auth::{ClientCredential, ClientCredentials, DelegatedAuth, MemoryTokenStore, TokenSet, TokenStore};
+use crate::fixture::Fixture;
+use crate::retry::RetryPolicy;
+use crate::timeout::Timeout;
+use crate::{
+    default_http_client, try_shared_oauth2_flow, ApiVersion, Cloud, MicrosoftAuth, MicrosoftClient, MicrosoftError, MicrosoftResult,
+};
+use httpclient_oauth2::OAuth2Flow;
+use std::borrow::Cow;
+use std::sync::Arc;
+use std::time::Duration;
+
+enum PendingAuth {
+    Auth(MicrosoftAuth),
+    OAuth2 {
+        store: Arc<dyn TokenStore>,
+    },
+    ClientCredentials {
+        tenant: String,
+        client_id: String,
+        credential: ClientCredential,
+    },
+}
+
+/// Configures a [`MicrosoftClient`] without touching the process-wide defaults, so one process can use
+/// several app registrations side by side.
+#[derive(Default)]
+pub struct MicrosoftClientBuilder {
+    http_client: Option<httpclient::Client>,
+    base_url: Option<String>,
+    cloud: Option<Cloud>,
+    api_version: Option<ApiVersion>,
+    default_headers: Vec<(String, String)>,
+    oauth2_flow: Option<OAuth2Flow>,
+    auth: Option<PendingAuth>,
+    retry: Option<RetryPolicy>,
+    timeout: Option<Duration>,
+    refresh_margin: Option<Duration>,
+    preflight: bool,
+    fixture: Option<Fixture>,
+}
+
+impl MicrosoftClientBuilder {
+    /// Start from this http client instead of [`default_http_client`].
+    pub fn http_client(mut self, client: httpclient::Client) -> Self {
+        self.http_client = Some(client);
+        self
+    }
+
+    /// Overrides the url derived from [`MicrosoftClientBuilder::cloud`] and [`MicrosoftClientBuilder::api_version`],
+    /// e.g. for a proxy.
+    pub fn base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = Some(base_url.into());
+        self
+    }
+
+    /// Sets both the Graph host and, unless an explicit [`MicrosoftClientBuilder::oauth2_flow`] is given,
+    /// the login host. Defaults to [`Cloud::Global`].
+    pub fn cloud(mut self, cloud: Cloud) -> Self {
+        self.cloud = Some(cloud);
+        self
+    }
+
+    /// The api version for every request of this client. Individual requests can override it.
+    pub fn api_version(mut self, version: ApiVersion) -> Self {
+        self.api_version = Some(version);
+        self
+    }
+
+    /// Sent with every request, e.g. `client-request-id` or `ConsistencyLevel`.
+    pub fn default_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.default_headers.push((name.into(), value.into()));
+        self
+    }
+
+    /// The app registration used by [`MicrosoftClientBuilder::oauth2`]. Defaults to the [`crate::shared_oauth2_flow`],
+    /// or for other clouds, [`Cloud::oauth2_flow_from_env`].
+    pub fn oauth2_flow(mut self, flow: OAuth2Flow) -> Self {
+        self.oauth2_flow = Some(flow);
+        self
+    }
+
+    /// Authenticate with an existing access and refresh token. Refreshed tokens are only kept in memory; see
+    /// [`MicrosoftClientBuilder::oauth2_store`] to persist them.
+    pub fn oauth2(self, access: impl Into<String>, refresh: impl Into<String>) -> Self {
+        let tokens = TokenSet::from_pair(access.into(), refresh.into());
+        self.oauth2_store(Arc::new(MemoryTokenStore::new(tokens)))
+    }
+
+    /// Authenticate with the tokens in `store`, and save refreshed tokens there.
+    pub fn oauth2_store(mut self, store: Arc<dyn TokenStore>) -> Self {
+        self.auth = Some(PendingAuth::OAuth2 { store });
+        self
+    }
+
+    /// App-only authentication against the builder's [`Cloud`]. `credential` is the client secret or a
+    /// [`crate::auth::ClientCertificate`].
+    pub fn client_credentials(
+        mut self, tenant: impl Into<String>, client_id: impl Into<String>, credential: impl Into<ClientCredential>,
+    ) -> Self {
+        self.auth = Some(PendingAuth::ClientCredentials {
+            tenant: tenant.into(),
+            client_id: client_id.into(),
+            credential: credential.into(),
+        });
+        self
+    }
+
+    pub fn auth(mut self, auth: MicrosoftAuth) -> Self {
+        self.auth = Some(PendingAuth::Auth(auth));
+        self
+    }
+
+    /// Renew tokens this long before they expire. Defaults to 5 minutes. Doesn't apply to [`MicrosoftClientBuilder::auth`],
+    /// which is configured already.
+    pub fn refresh_margin(mut self, margin: Duration) -> Self {
+        self.refresh_margin = Some(margin);
+        self
+    }
+
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Some(policy);
+        self
+    }
+
+    /// Give up on a request attempt after this long. There is no timeout by default.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Check the access token's permissions before each request, and fail with
+    /// [`MicrosoftError::MissingPermissions`] instead of sending requests Graph would refuse. Multi-step requests
+    /// like [`MicrosoftClient::send_email`] then fail before any step has run. Off by default.
+    pub fn preflight(mut self, enabled: bool) -> Self {
+        self.preflight = enabled;
+        self
+    }
+
+    /// Record Graph interactions to, or replay them from, a fixture file. Only requests under the Graph base url
+    /// are recorded, so token requests aren't; replay with a [`crate::auth::StaticToken`].
+    pub fn fixture(mut self, fixture: Fixture) -> Self {
+        self.fixture = Some(fixture);
+        self
+    }
+
+    pub fn build(self) -> MicrosoftResult<MicrosoftClient> {
+        let authentication = match self.auth {
+            Some(PendingAuth::Auth(auth)) => auth,
+            Some(PendingAuth::OAuth2 { store }) => {
+                let cloud_flow;
+                let flow = match (&self.oauth2_flow, self.cloud) {
+                    (Some(flow), _) => flow,
+                    (None, Some(cloud)) if cloud != Cloud::Global => {
+                        cloud_flow = cloud.oauth2_flow_from_env()?;
+                        &cloud_flow
+                    }
+                    (None, _) => try_shared_oauth2_flow()?,
+                };
+                let mut middleware = DelegatedAuth::from_flow(flow, store);
+                if let Some(margin) = self.refresh_margin {
+                    middleware = middleware.refresh_margin(margin);
+                }
+                MicrosoftAuth::OAuth2 {
+                    middleware: Arc::new(middleware),
+                }
+            }
+            Some(PendingAuth::ClientCredentials {
+                tenant,
+                client_id,
+                credential,
+            }) => {
+                let cloud = self.cloud.unwrap_or_default();
+                let mut middleware = ClientCredentials::new(cloud, &tenant, client_id, credential);
+                if let Some(margin) = self.refresh_margin {
+                    middleware = middleware.refresh_margin(margin);
+                }
+                MicrosoftAuth::ClientCredentials {
+                    middleware: Arc::new(middleware),
+                }
+            }
+            None => return Err(MicrosoftError::Config("no authentication configured".to_string())),
+        };
+        let mut client = self.http_client.unwrap_or_else(default_http_client);
+        if let Some(base_url) = &self.base_url {
+            client = client.base_url(base_url);
+        } else if self.cloud.is_some() || self.api_version.is_some() {
+            let url = self.cloud.unwrap_or_default().graph_url(self.api_version.unwrap_or_default());
+            client = client.base_url(&url);
+        }
+        if !self.default_headers.is_empty() {
+            client = client.default_headers(self.default_headers.iter().map(|(k, v)| (k.as_str(), v.as_str())));
+        }
+        Ok(MicrosoftClient {
+            client: Cow::Owned(client),
+            authentication,
+            retry: Arc::new(self.retry.unwrap_or_default()),
+            timeout: self.timeout.map(|duration| Arc::new(Timeout { duration })),
+            preflight: self.preflight,
+            fixture: self.fixture.map(|fixture| {
+                let graph_url = match &self.base_url {
+                    Some(base_url) => base_url.clone(),
+                    None => self.cloud.unwrap_or_default().graph_host().to_string(),
+                };
+                Arc::new(fixture.graph_url(graph_url))
+            }),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build() {
+        let flow = Cloud::UsGovL4.oauth2_flow("common", "id", "secret", "https://example.com/callback");
+        let client = MicrosoftClient::builder()
+            .oauth2_flow(flow)
+            .oauth2("access", "refresh")
+            .cloud(Cloud::UsGovL4)
+            .api_version(ApiVersion::Beta)
+            .build()
+            .unwrap();
+        let MicrosoftAuth::OAuth2 { middleware } = &client.authentication else {
+            panic!("expected oauth2")
+        };
+        assert_eq!(middleware.token_endpoint(), "https://login.microsoftonline.us/common/oauth2/v2.0/token");
+        let r = client.client.get("/me");
+        assert_eq!(r.uri.to_string(), "https://graph.microsoft.us/beta/me");
+        assert!(matches!(MicrosoftClient::builder().build(), Err(MicrosoftError::Config(_))));
+    }
+}
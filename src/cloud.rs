@@ -0,0 +1,152 @@
+use crate::{MicrosoftError, MicrosoftResult};
+use httpclient::Uri;
+use httpclient_oauth2::OAuth2Flow;
+use std::fmt;
+
+/// The Microsoft cloud a tenant lives in. Graph and login hosts differ per cloud, and tokens from one
+/// are rejected by the others.
+/// see https://learn.microsoft.com/en-us/graph/deployments
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum Cloud {
+    #[default]
+    Global,
+    /// US Government L4, a.k.a. GCC High.
+    UsGovL4,
+    /// US Government L5, a.k.a. DoD.
+    UsGovL5,
+    /// China, operated by 21Vianet.
+    China,
+}
+
+impl Cloud {
+    pub fn graph_host(&self) -> &'static str {
+        match self {
+            Cloud::Global => "https://graph.microsoft.com",
+            Cloud::UsGovL4 => "https://graph.microsoft.us",
+            Cloud::UsGovL5 => "https://dod-graph.microsoft.us",
+            Cloud::China => "https://microsoftgraph.chinacloudapi.cn",
+        }
+    }
+
+    pub fn login_host(&self) -> &'static str {
+        match self {
+            Cloud::Global => "https://login.microsoftonline.com",
+            Cloud::UsGovL4 | Cloud::UsGovL5 => "https://login.microsoftonline.us",
+            Cloud::China => "https://login.chinacloudapi.cn",
+        }
+    }
+
+    /// The base url for Graph requests, e.g. `https://graph.microsoft.us/v1.0`.
+    pub fn graph_url(&self, version: ApiVersion) -> String {
+        format!("{}/{version}", self.graph_host())
+    }
+
+    /// `tenant` is a tenant id or domain, or `common`/`organizations` for multi-tenant apps.
+    pub fn authorize_endpoint(&self, tenant: &str) -> String {
+        format!("{}/{tenant}/oauth2/v2.0/authorize", self.login_host())
+    }
+
+    pub fn token_endpoint(&self, tenant: &str) -> String {
+        format!("{}/{tenant}/oauth2/v2.0/token", self.login_host())
+    }
+
+    /// `tenant` is the customer's tenant id or domain, or `organizations` to let the administrator's sign in decide.
+    pub fn admin_consent_endpoint(&self, tenant: &str) -> String {
+        format!("{}/{tenant}/v2.0/adminconsent", self.login_host())
+    }
+
+    pub fn oauth2_flow(
+        &self, tenant: &str, client_id: impl Into<String>, client_secret: impl Into<String>, redirect_uri: impl Into<String>,
+    ) -> OAuth2Flow {
+        OAuth2Flow {
+            client_id: client_id.into(),
+            client_secret: client_secret.into(),
+            init_endpoint: self.authorize_endpoint(tenant),
+            exchange_endpoint: self.token_endpoint(tenant),
+            refresh_endpoint: self.token_endpoint(tenant),
+            redirect_uri: redirect_uri.into(),
+        }
+    }
+
+    /// Read the app registration from `MICROSOFT_CLIENT_ID`, `MICROSOFT_CLIENT_SECRET`, `MICROSOFT_REDIRECT_URI`
+    /// and optionally `MICROSOFT_TENANT_ID` (default `common`).
+    pub fn oauth2_flow_from_env(&self) -> MicrosoftResult<OAuth2Flow> {
+        let var = |name: &str| std::env::var(name).map_err(|_| MicrosoftError::Config(format!("{name} must be set")));
+        let tenant = std::env::var("MICROSOFT_TENANT_ID").unwrap_or_else(|_| "common".to_string());
+        Ok(self.oauth2_flow(
+            &tenant,
+            var("MICROSOFT_CLIENT_ID")?,
+            var("MICROSOFT_CLIENT_SECRET")?,
+            var("MICROSOFT_REDIRECT_URI")?,
+        ))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum ApiVersion {
+    #[default]
+    V1,
+    /// Endpoints that aren't in v1.0 yet. Beta APIs can change without notice, so don't use them more than needed.
+    Beta,
+}
+
+impl ApiVersion {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ApiVersion::V1 => "v1.0",
+            ApiVersion::Beta => "beta",
+        }
+    }
+
+    /// Point a Graph url at this version, e.g. `https://graph.microsoft.com/v1.0/me` -> `https://graph.microsoft.com/beta/me`.
+    /// Urls without a version segment are returned unchanged.
+    pub(crate) fn rewrite(&self, uri: &Uri) -> Uri {
+        let Some(path_and_query) = uri.path_and_query() else {
+            return uri.clone();
+        };
+        let pq = path_and_query.as_str();
+        let rest = [ApiVersion::V1, ApiVersion::Beta]
+            .iter()
+            .find_map(|v| pq.strip_prefix('/').and_then(|p| p.strip_prefix(v.as_str())))
+            .filter(|rest| rest.is_empty() || rest.starts_with(['/', '?']));
+        let Some(rest) = rest else {
+            return uri.clone();
+        };
+        let mut parts = uri.clone().into_parts();
+        parts.path_and_query = format!("/{self}{rest}").parse().ok();
+        Uri::from_parts(parts).unwrap_or_else(|_| uri.clone())
+    }
+}
+
+impl fmt::Display for ApiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_endpoints() {
+        assert_eq!(Cloud::UsGovL5.graph_url(ApiVersion::V1), "https://dod-graph.microsoft.us/v1.0");
+        assert_eq!(
+            Cloud::China.token_endpoint("contoso.onmicrosoft.com"),
+            "https://login.chinacloudapi.cn/contoso.onmicrosoft.com/oauth2/v2.0/token"
+        );
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let uri: Uri = "https://graph.microsoft.com/v1.0/me/messages?$top=5".parse().unwrap();
+        assert_eq!(
+            ApiVersion::Beta.rewrite(&uri).to_string(),
+            "https://graph.microsoft.com/beta/me/messages?$top=5"
+        );
+        let uri: Uri = "https://graph.microsoft.com/v1.0".parse().unwrap();
+        assert_eq!(ApiVersion::Beta.rewrite(&uri).to_string(), "https://graph.microsoft.com/beta");
+        let uri: Uri = "https://example.com/v1.0x/me".parse().unwrap();
+        assert_eq!(ApiVersion::Beta.rewrite(&uri), uri);
+    }
+}
@@ -0,0 +1,216 @@
+//! Decryption of rich notifications, i.e. subscriptions created with `includeResourceData`.
+//!
+//! Graph encrypts the resource with a random symmetric key, and encrypts that key with the public key
+//! of the certificate registered on the subscription.
+//! see https://learn.microsoft.com/en-us/graph/change-notifications-with-resource-data#decrypting-resource-data-from-change-notifications
+use crate::model::{EmailMessage, EncryptedContent, Notification};
+use aes::cipher::block_padding::Pkcs7;
+use aes::cipher::{BlockDecryptMut, KeyIvInit};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, Mac};
+use rsa::pkcs1::DecodeRsaPrivateKey;
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::{Oaep, RsaPrivateKey};
+use serde::de::DeserializeOwned;
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use std::fmt;
+
+type Aes256CbcDec = cbc::Decryptor<aes::Aes256>;
+
+#[derive(Debug)]
+pub enum DecryptionError {
+    InvalidCertificate(String),
+    InvalidPrivateKey(String),
+    /// The notification was encrypted for a different certificate, e.g. one that has since been rotated out.
+    CertificateMismatch { expected: String, received: String },
+    /// The notification carries no `encryptedContent`. Was the subscription created with `includeResourceData`?
+    MissingContent,
+    Base64(base64::DecodeError),
+    KeyDecryption(rsa::Error),
+    /// The HMAC over the payload doesn't match `dataSignature`. The payload was tampered with or corrupted.
+    SignatureMismatch,
+    PayloadDecryption,
+    Json(serde_json::Error),
+}
+
+impl fmt::Display for DecryptionError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecryptionError::InvalidCertificate(e) => write!(f, "Invalid certificate: {e}"),
+            DecryptionError::InvalidPrivateKey(e) => write!(f, "Invalid private key: {e}"),
+            DecryptionError::CertificateMismatch { expected, received } => {
+                write!(f, "Notification was encrypted for certificate {received}, expected {expected}")
+            }
+            DecryptionError::MissingContent => write!(f, "Notification has no encryptedContent"),
+            DecryptionError::Base64(e) => write!(f, "Invalid base64: {e}"),
+            DecryptionError::KeyDecryption(e) => write!(f, "Failed to decrypt dataKey: {e}"),
+            DecryptionError::SignatureMismatch => write!(f, "dataSignature does not match the payload"),
+            DecryptionError::PayloadDecryption => write!(f, "Failed to decrypt data"),
+            DecryptionError::Json(e) => write!(f, "Failed to parse decrypted data: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for DecryptionError {}
+
+impl From<base64::DecodeError> for DecryptionError {
+    fn from(value: base64::DecodeError) -> Self {
+        DecryptionError::Base64(value)
+    }
+}
+
+impl From<serde_json::Error> for DecryptionError {
+    fn from(value: serde_json::Error) -> Self {
+        DecryptionError::Json(value)
+    }
+}
+
+/// The certificate Graph encrypts rich notifications with. Graph only ever sees the public certificate;
+/// the private key stays here to decrypt the payloads.
+pub struct NotificationCertificate {
+    id: String,
+    certificate_der: Vec<u8>,
+    private_key: RsaPrivateKey,
+}
+
+impl fmt::Debug for NotificationCertificate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NotificationCertificate")
+            .field("id", &self.id)
+            .field("thumbprint", &self.thumbprint())
+            // .field("private_key", &self.private_key)
+            .finish()
+    }
+}
+
+impl NotificationCertificate {
+    /// `id` is any identifier you choose. Graph echoes it back on every notification as `encryptionCertificateId`,
+    /// which is how you tell certificates apart while rotating them.
+    /// The private key can be PKCS#8 (`BEGIN PRIVATE KEY`) or PKCS#1 (`BEGIN RSA PRIVATE KEY`).
+    pub fn from_pem(id: impl Into<String>, certificate_pem: &str, private_key_pem: &str) -> Result<Self, DecryptionError> {
+        let certificate = pem::parse(certificate_pem).map_err(|e| DecryptionError::InvalidCertificate(e.to_string()))?;
+        if certificate.tag() != "CERTIFICATE" {
+            return Err(DecryptionError::InvalidCertificate(format!("expected CERTIFICATE, got {}", certificate.tag())));
+        }
+        let private_key = RsaPrivateKey::from_pkcs8_pem(private_key_pem)
+            .or_else(|_| RsaPrivateKey::from_pkcs1_pem(private_key_pem))
+            .map_err(|e| DecryptionError::InvalidPrivateKey(e.to_string()))?;
+        Ok(Self {
+            id: id.into(),
+            certificate_der: certificate.into_contents(),
+            private_key,
+        })
+    }
+
+    /// A certificate with a fresh key and a placeholder certificate, for tests that don't talk to Graph.
+    #[cfg(test)]
+    pub(crate) fn generate(id: &str) -> Self {
+        Self {
+            id: id.to_string(),
+            certificate_der: vec![1, 2, 3],
+            private_key: RsaPrivateKey::new(&mut rsa::rand_core::OsRng, 1024).unwrap(),
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The base64 encoded DER certificate, as Graph expects it in `encryptionCertificate`.
+    pub fn encryption_certificate(&self) -> String {
+        STANDARD.encode(&self.certificate_der)
+    }
+
+    /// Upper case hex SHA-1 of the certificate, as Graph sends it in `encryptionCertificateThumbprint`.
+    pub fn thumbprint(&self) -> String {
+        Sha1::digest(&self.certificate_der).iter().map(|b| format!("{b:02X}")).collect()
+    }
+
+    /// Decrypt the raw resource JSON.
+    pub fn decrypt(&self, content: &EncryptedContent) -> Result<Vec<u8>, DecryptionError> {
+        if content.encryption_certificate_id != self.id {
+            return Err(DecryptionError::CertificateMismatch {
+                expected: self.id.clone(),
+                received: content.encryption_certificate_id.clone(),
+            });
+        }
+        let data_key = STANDARD.decode(&content.data_key)?;
+        let key = self
+            .private_key
+            .decrypt(Oaep::new::<Sha1>(), &data_key)
+            .map_err(DecryptionError::KeyDecryption)?;
+        let mut data = STANDARD.decode(&content.data)?;
+        let signature = STANDARD.decode(&content.data_signature)?;
+        let mut mac = Hmac::<Sha256>::new_from_slice(&key).map_err(|_| DecryptionError::PayloadDecryption)?;
+        mac.update(&data);
+        mac.verify_slice(&signature).map_err(|_| DecryptionError::SignatureMismatch)?;
+        // the IV is the first 16 bytes of the symmetric key
+        let iv = key.get(..16).ok_or(DecryptionError::PayloadDecryption)?;
+        let decryptor = Aes256CbcDec::new_from_slices(&key, iv).map_err(|_| DecryptionError::PayloadDecryption)?;
+        let plaintext = decryptor
+            .decrypt_padded_mut::<Pkcs7>(&mut data)
+            .map_err(|_| DecryptionError::PayloadDecryption)?;
+        Ok(plaintext.to_vec())
+    }
+}
+
+impl Notification {
+    /// Decrypt the resource Graph attached to the notification.
+    pub fn decrypt_resource<T: DeserializeOwned>(&self, certificate: &NotificationCertificate) -> Result<T, DecryptionError> {
+        let content = self.encrypted_content.as_ref().ok_or(DecryptionError::MissingContent)?;
+        let data = certificate.decrypt(content)?;
+        serde_json::from_slice(&data).map_err(Into::into)
+    }
+
+    /// Decrypt the message attached to the notification, saving the follow-up GET.
+    pub fn decrypt_message(&self, certificate: &NotificationCertificate) -> Result<EmailMessage, DecryptionError> {
+        self.decrypt_resource(certificate)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aes::cipher::BlockEncryptMut;
+    use rsa::rand_core::OsRng;
+    use rsa::RsaPublicKey;
+
+    fn encrypt(public_key: &RsaPublicKey, key: &[u8; 32], plaintext: &[u8]) -> EncryptedContent {
+        let mut buf = plaintext.to_vec();
+        buf.resize(plaintext.len() + 16, 0);
+        let data = cbc::Encryptor::<aes::Aes256>::new_from_slices(key, &key[..16])
+            .unwrap()
+            .encrypt_padded_mut::<Pkcs7>(&mut buf, plaintext.len())
+            .unwrap()
+            .to_vec();
+        let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+        mac.update(&data);
+        EncryptedContent {
+            data: STANDARD.encode(&data),
+            data_signature: STANDARD.encode(mac.finalize().into_bytes()),
+            data_key: STANDARD.encode(public_key.encrypt(&mut OsRng, Oaep::new::<Sha1>(), key).unwrap()),
+            encryption_certificate_id: "cert".to_string(),
+            encryption_certificate_thumbprint: None,
+        }
+    }
+
+    #[test]
+    fn test_decrypt() {
+        let private_key = RsaPrivateKey::new(&mut OsRng, 1024).unwrap();
+        let certificate = NotificationCertificate {
+            id: "cert".to_string(),
+            certificate_der: vec![1, 2, 3],
+            private_key,
+        };
+        let public_key = certificate.private_key.to_public_key();
+        let mut content = encrypt(&public_key, &[7; 32], br#"{"id":"abc"}"#);
+        assert_eq!(certificate.decrypt(&content).unwrap(), br#"{"id":"abc"}"#);
+
+        content.data_signature = STANDARD.encode([0; 32]);
+        assert!(matches!(certificate.decrypt(&content), Err(DecryptionError::SignatureMismatch)));
+
+        content.encryption_certificate_id = "other".to_string();
+        assert!(matches!(certificate.decrypt(&content), Err(DecryptionError::CertificateMismatch { .. })));
+    }
+}
@@ -0,0 +1,303 @@
+use crate::auth::Scope;
+use crate::retry::parse_retry_after;
+use httpclient::{header, InMemoryBody, InMemoryError, InMemoryResponse, ProtocolError, StatusCode};
+use serde::Deserialize;
+use serde_json::Value;
+use std::fmt;
+use std::time::Duration;
+
+pub type MicrosoftResult<T> = Result<T, MicrosoftError>;
+
+/// The error object Graph returns with every failed request.
+/// see https://learn.microsoft.com/en-us/graph/errors
+#[derive(Debug, Clone, Default)]
+pub struct GraphError {
+    pub status: StatusCode,
+    /// e.g. `ErrorItemNotFound`, `MailboxNotEnabledForRESTAPI`
+    pub code: String,
+    pub message: String,
+    /// Include this in Microsoft support tickets.
+    pub request_id: Option<String>,
+    pub client_request_id: Option<String>,
+    pub date: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ErrorEnvelope {
+    error: ErrorBody,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ErrorBody {
+    #[serde(default)]
+    code: String,
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    inner_error: Option<InnerError>,
+}
+
+#[derive(Deserialize)]
+struct InnerError {
+    #[serde(rename = "request-id")]
+    request_id: Option<String>,
+    #[serde(rename = "client-request-id")]
+    client_request_id: Option<String>,
+    date: Option<String>,
+}
+
+/// The login endpoint reports errors in the OAuth2 format instead.
+#[derive(Deserialize)]
+struct OAuth2ErrorBody {
+    error: String,
+    #[serde(default)]
+    error_description: String,
+    trace_id: Option<String>,
+    correlation_id: Option<String>,
+    timestamp: Option<String>,
+}
+
+/// OAuth2 error codes that mean the credentials, not the request, are the problem.
+const AUTH_ERROR_CODES: &[&str] = &[
+    "invalid_client",
+    "invalid_grant",
+    "unauthorized_client",
+    "invalid_scope",
+    "interaction_required",
+    "consent_required",
+];
+
+impl GraphError {
+    /// Parse the error from a response body. Bodies that aren't Graph errors (e.g. from a proxy) keep their text
+    /// as the message.
+    pub fn from_body(status: StatusCode, body: &Value) -> Self {
+        if let Ok(e) = OAuth2ErrorBody::deserialize(body) {
+            return Self {
+                status,
+                code: e.error,
+                message: e.error_description,
+                request_id: e.trace_id,
+                client_request_id: e.correlation_id,
+                date: e.timestamp,
+            };
+        }
+        let Ok(envelope) = ErrorEnvelope::deserialize(body) else {
+            let message = match body {
+                Value::Null => String::new(),
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            };
+            return Self {
+                status,
+                message,
+                ..Self::default()
+            };
+        };
+        let inner = envelope.error.inner_error;
+        Self {
+            status,
+            code: envelope.error.code,
+            message: envelope.error.message,
+            request_id: inner.as_ref().and_then(|i| i.request_id.clone()),
+            client_request_id: inner.as_ref().and_then(|i| i.client_request_id.clone()),
+            date: inner.and_then(|i| i.date),
+        }
+    }
+}
+
+impl fmt::Display for GraphError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.status)?;
+        if !self.code.is_empty() {
+            write!(f, " {}", self.code)?;
+        }
+        if !self.message.is_empty() {
+            write!(f, ": {}", self.message)?;
+        }
+        if let Some(request_id) = &self.request_id {
+            write!(f, " (request-id: {request_id})")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+pub enum MicrosoftError {
+    /// 401. The access token is invalid or expired, and refreshing it didn't help.
+    Auth(Box<GraphError>),
+    /// Graph kept throttling the request after the [`crate::retry::RetryPolicy`] gave up.
+    Throttled {
+        error: Box<GraphError>,
+        retry_after: Option<Duration>,
+    },
+    NotFound(Box<GraphError>),
+    /// 403. Usually the token lacks a scope, or the app lacks admin consent, for the resource.
+    Forbidden(Box<GraphError>),
+    /// The user has no Exchange Online mailbox, e.g. an unlicensed or on-premise account.
+    MailboxNotEnabled(Box<GraphError>),
+    /// Any other error response from Graph.
+    Graph(Box<GraphError>),
+    /// The request failed before Graph answered, or the answer couldn't be read.
+    Protocol(ProtocolError),
+    /// Graph answered successfully, but not with what we expected.
+    UnexpectedResponse(String),
+    /// The client is misconfigured, e.g. the app registration is missing.
+    Config(String),
+    /// The access token lacks these permissions, so the request wasn't sent. See [`crate::MicrosoftClientBuilder::preflight`].
+    MissingPermissions(Vec<Scope>),
+}
+
+impl MicrosoftError {
+    pub fn graph_error(&self) -> Option<&GraphError> {
+        match self {
+            MicrosoftError::Auth(e)
+            | MicrosoftError::Throttled { error: e, .. }
+            | MicrosoftError::NotFound(e)
+            | MicrosoftError::Forbidden(e)
+            | MicrosoftError::MailboxNotEnabled(e)
+            | MicrosoftError::Graph(e) => Some(e),
+            MicrosoftError::Protocol(_)
+            | MicrosoftError::UnexpectedResponse(_)
+            | MicrosoftError::Config(_)
+            | MicrosoftError::MissingPermissions(_) => None,
+        }
+    }
+
+    pub fn status(&self) -> Option<StatusCode> {
+        self.graph_error().map(|e| e.status)
+    }
+
+    pub fn request_id(&self) -> Option<&str> {
+        self.graph_error().and_then(|e| e.request_id.as_deref())
+    }
+
+    pub(crate) fn from_graph_error(error: GraphError, retry_after: Option<Duration>) -> Self {
+        let error = Box::new(error);
+        match (error.status, error.code.as_str()) {
+            (_, "MailboxNotEnabledForRESTAPI" | "MailboxNotSupportedForRESTAPI") => MicrosoftError::MailboxNotEnabled(error),
+            (StatusCode::UNAUTHORIZED, _) => MicrosoftError::Auth(error),
+            (_, code) if AUTH_ERROR_CODES.contains(&code) => MicrosoftError::Auth(error),
+            (StatusCode::FORBIDDEN, _) => MicrosoftError::Forbidden(error),
+            (StatusCode::NOT_FOUND, _) => MicrosoftError::NotFound(error),
+            (StatusCode::TOO_MANY_REQUESTS, _) => MicrosoftError::Throttled { error, retry_after },
+            (StatusCode::SERVICE_UNAVAILABLE, _) if retry_after.is_some() => MicrosoftError::Throttled { error, retry_after },
+            _ => MicrosoftError::Graph(error),
+        }
+    }
+
+    fn from_response(res: InMemoryResponse) -> Self {
+        let status = res.status();
+        let header = |name: &str| res.headers().get(name).and_then(|v| v.to_str().ok()).map(str::to_string);
+        let request_id = header("request-id");
+        let client_request_id = header("client-request-id");
+        let date = header("date");
+        let retry_after = header(header::RETRY_AFTER.as_str()).and_then(|v| parse_retry_after(&v));
+        let (_, body) = res.into_parts();
+        let body = match body {
+            InMemoryBody::Json(v) => v,
+            InMemoryBody::Text(s) => serde_json::from_str(&s).unwrap_or(Value::String(s)),
+            InMemoryBody::Bytes(b) => serde_json::from_slice(&b).unwrap_or(Value::Null),
+            InMemoryBody::Empty => Value::Null,
+        };
+        let mut error = GraphError::from_body(status, &body);
+        error.request_id = error.request_id.or(request_id);
+        error.client_request_id = error.client_request_id.or(client_request_id);
+        error.date = error.date.or(date);
+        Self::from_graph_error(error, retry_after)
+    }
+}
+
+impl fmt::Display for MicrosoftError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MicrosoftError::Auth(e) => write!(f, "Authentication failed: {e}"),
+            MicrosoftError::Throttled { error, .. } => write!(f, "Throttled: {error}"),
+            MicrosoftError::NotFound(e) => write!(f, "Not found: {e}"),
+            MicrosoftError::Forbidden(e) => write!(f, "Forbidden: {e}"),
+            MicrosoftError::MailboxNotEnabled(e) => write!(f, "Mailbox not enabled: {e}"),
+            MicrosoftError::Graph(e) => write!(f, "Graph error: {e}"),
+            MicrosoftError::Protocol(e) => write!(f, "Protocol error: {e}"),
+            MicrosoftError::UnexpectedResponse(e) => write!(f, "Unexpected response: {e}"),
+            MicrosoftError::Config(e) => write!(f, "Invalid configuration: {e}"),
+            MicrosoftError::MissingPermissions(scopes) => {
+                let scopes = scopes.iter().map(Scope::as_str).collect::<Vec<_>>();
+                write!(f, "{} missing", scopes.join(", "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for MicrosoftError {}
+
+impl MicrosoftError {
+    /// Carry an error out of a middleware, which can only fail with a [`ProtocolError`]. Converting back into a
+    /// `MicrosoftError` unwraps it again.
+    pub(crate) fn into_protocol_error(self) -> ProtocolError {
+        ProtocolError::IoError(std::io::Error::other(self))
+    }
+}
+
+impl From<InMemoryError> for MicrosoftError {
+    fn from(value: InMemoryError) -> Self {
+        match value {
+            InMemoryError::HttpError(res) => MicrosoftError::from_response(res),
+            InMemoryError::Protocol(e) => MicrosoftError::from(e),
+        }
+    }
+}
+
+impl From<ProtocolError> for MicrosoftError {
+    fn from(value: ProtocolError) -> Self {
+        match value {
+            ProtocolError::IoError(e) if e.get_ref().is_some_and(|inner| inner.is::<MicrosoftError>()) => {
+                *e.into_inner().unwrap().downcast::<MicrosoftError>().unwrap()
+            }
+            e => MicrosoftError::Protocol(e),
+        }
+    }
+}
+
+impl From<serde_json::Error> for MicrosoftError {
+    fn from(value: serde_json::Error) -> Self {
+        MicrosoftError::Protocol(ProtocolError::JsonError(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use httpclient::InMemoryResponseExt;
+
+    #[test]
+    fn test_graph_error() {
+        let body = serde_json::json!({
+            "error": {
+                "code": "MailboxNotEnabledForRESTAPI",
+                "message": "The mailbox is either inactive, soft-deleted, or is hosted on-premise.",
+                "innerError": {
+                    "date": "2024-11-20T18:23:45",
+                    "request-id": "2b1a4c7c-8f0d-4f6a-9a7e-123456789abc",
+                    "client-request-id": "2b1a4c7c-8f0d-4f6a-9a7e-123456789abc"
+                }
+            }
+        });
+        let headers = Default::default();
+        let res = <InMemoryResponse as InMemoryResponseExt>::new(StatusCode::NOT_FOUND, headers, InMemoryBody::Json(body));
+        let err = MicrosoftError::from(InMemoryError::HttpError(res));
+        assert!(matches!(err, MicrosoftError::MailboxNotEnabled(_)));
+        assert_eq!(err.request_id(), Some("2b1a4c7c-8f0d-4f6a-9a7e-123456789abc"));
+
+        let err = MicrosoftError::from_graph_error(GraphError::from_body(StatusCode::FORBIDDEN, &Value::Null), None);
+        assert!(matches!(err, MicrosoftError::Forbidden(_)));
+
+        let body = serde_json::json!({
+            "error": "invalid_client",
+            "error_description": "AADSTS7000215: Invalid client secret provided.",
+            "trace_id": "0c3f6b0e-1d2a-4c2b-8a34-5b6a7c8d9e0f"
+        });
+        let err = MicrosoftError::from_graph_error(GraphError::from_body(StatusCode::BAD_REQUEST, &body), None);
+        assert!(matches!(err, MicrosoftError::Auth(_)));
+        assert_eq!(err.request_id(), Some("0c3f6b0e-1d2a-4c2b-8a34-5b6a7c8d9e0f"));
+    }
+}
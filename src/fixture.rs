@@ -0,0 +1,456 @@
+//! Record Graph interactions to a fixture file, then replay them in tests that run offline.
+//!
+//! ```no_run
+//! # async fn example() -> microsoft_mail::MicrosoftResult<()> {
+//! use microsoft_mail::auth::StaticToken;
+//! use microsoft_mail::fixture::Fixture;
+//! use microsoft_mail::{MicrosoftAuth, MicrosoftClient};
+//!
+//! // replays, unless MICROSOFT_RECORD_FIXTURES=1 is set
+//! let fixture = Fixture::from_env("tests/fixtures/list_messages.json")?;
+//! let auth = match fixture.is_recording() {
+//!     true => MicrosoftAuth::oauth2(std::env::var("ACCESS").unwrap(), std::env::var("REFRESH").unwrap()),
+//!     false => MicrosoftAuth::provider(StaticToken::new("replay")),
+//! };
+//! let client = MicrosoftClient::builder().auth(auth).fixture(fixture).build()?;
+//! let page = client.list_messages().top(5).await?;
+//! # Ok(())
+//! # }
+//! ```
+use crate::{MicrosoftError, MicrosoftResult};
+use async_trait::async_trait;
+use httpclient::{header, Body, InMemoryBody, InMemoryRequest, Middleware, Next, ProtocolResult, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::{fmt, fs};
+
+/// Set to `1` to make [`Fixture::from_env`] record instead of replay.
+pub const RECORD_ENV_VAR: &str = "MICROSOFT_RECORD_FIXTURES";
+
+/// What stands in for redacted secrets and names.
+pub const REDACTED: &str = "REDACTED";
+
+/// Response headers worth keeping. Everything else, on requests too, is dropped, so no credentials or cookies
+/// end up in a fixture.
+const KEPT_HEADERS: &[&str] = &["content-type", "location", "retry-after"];
+
+/// Fields whose values are credentials, wherever they appear.
+const SECRET_FIELDS: &[&str] = &[
+    "access_token",
+    "refresh_token",
+    "id_token",
+    "client_secret",
+    "client_assertion",
+    "assertion",
+    "password",
+    "clientState",
+    "validationToken",
+];
+
+/// Fields whose values are people's names.
+const NAME_FIELDS: &[&str] = &["displayName", "givenName", "surname"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FixtureMode {
+    /// Send requests, and save every interaction to the fixture file, replacing what it held.
+    Record,
+    /// Answer requests from the fixture file. Nothing is sent.
+    Replay,
+}
+
+/// A middleware that records or replays Graph interactions, see [`crate::MicrosoftClientBuilder::fixture`].
+///
+/// Recordings are redacted: request headers are dropped, tokens and secrets are replaced with [`REDACTED`], and
+/// email addresses become `user-{hash}@example.com`. The hash keeps the same address recognizable across a
+/// fixture, and requests addressing a mailbox by email replay against the redacted url. Display names are
+/// replaced too; subjects and bodies are kept, so record against a test mailbox.
+///
+/// Requests are matched on method and url, in recorded order, so repeated requests replay their own responses.
+/// Requests outside the client's Graph base url, like token requests, are passed through untouched.
+pub struct Fixture {
+    path: PathBuf,
+    mode: FixtureMode,
+    // set by the client builder. `None` records and replays every request
+    graph_url: Option<String>,
+    interactions: Mutex<Vec<Interaction>>,
+    // replay only: which interactions were already used
+    used: Mutex<Vec<bool>>,
+}
+
+impl fmt::Debug for Fixture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Fixture")
+            .field("path", &self.path)
+            .field("mode", &self.mode)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct FixtureFile {
+    interactions: Vec<Interaction>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Interaction {
+    request: RecordedRequest,
+    response: RecordedResponse,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordedRequest {
+    method: String,
+    /// Path and query, without the host.
+    url: String,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    body: Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordedResponse {
+    status: u16,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    headers: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "Value::is_null")]
+    body: Value,
+}
+
+impl Fixture {
+    /// Start a new recording at `path`. The file is written after every interaction.
+    pub fn record(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            mode: FixtureMode::Record,
+            graph_url: None,
+            interactions: Mutex::default(),
+            used: Mutex::default(),
+        }
+    }
+
+    /// Load the recording at `path`.
+    pub fn replay(path: impl Into<PathBuf>) -> MicrosoftResult<Self> {
+        let path = path.into();
+        let data = fs::read(&path).map_err(|e| MicrosoftError::Config(format!("Fixture {}: {e}", path.display())))?;
+        let file: FixtureFile =
+            serde_json::from_slice(&data).map_err(|e| MicrosoftError::Config(format!("Fixture {}: {e}", path.display())))?;
+        Ok(Self {
+            path,
+            mode: FixtureMode::Replay,
+            graph_url: None,
+            used: Mutex::new(vec![false; file.interactions.len()]),
+            interactions: Mutex::new(file.interactions),
+        })
+    }
+
+    /// Record if [`RECORD_ENV_VAR`] is `1`, otherwise replay.
+    pub fn from_env(path: impl Into<PathBuf>) -> MicrosoftResult<Self> {
+        match std::env::var(RECORD_ENV_VAR).as_deref() {
+            Ok("1") => Ok(Self::record(path)),
+            _ => Self::replay(path),
+        }
+    }
+
+    pub fn mode(&self) -> FixtureMode {
+        self.mode
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.mode == FixtureMode::Record
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Only record and replay requests under `graph_url`.
+    pub(crate) fn graph_url(mut self, graph_url: impl Into<String>) -> Self {
+        self.graph_url = Some(graph_url.into());
+        self
+    }
+
+    fn save(&self, interactions: &[Interaction]) -> MicrosoftResult<()> {
+        let file = FixtureFile {
+            interactions: interactions.to_vec(),
+        };
+        let data = serde_json::to_vec_pretty(&file).expect("Failed to serialize fixture");
+        let write = || {
+            if let Some(parent) = self.path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            fs::write(&self.path, data)
+        };
+        write().map_err(|e| MicrosoftError::Config(format!("Fixture {}: {e}", self.path.display())))
+    }
+
+    fn find(&self, method: &str, url: &str) -> MicrosoftResult<RecordedResponse> {
+        let interactions = self.interactions.lock().unwrap();
+        let mut used = self.used.lock().unwrap();
+        let position = interactions
+            .iter()
+            .zip(used.iter())
+            .position(|(i, used)| !used && i.request.method == method && i.request.url == url)
+            .ok_or_else(|| {
+                MicrosoftError::Config(format!("Fixture {} has no recorded response for {method} {url}", self.path.display()))
+            })?;
+        used[position] = true;
+        Ok(interactions[position].response.clone())
+    }
+}
+
+#[async_trait]
+impl Middleware for Fixture {
+    async fn handle(&self, request: InMemoryRequest, next: Next<'_>) -> ProtocolResult<Response> {
+        if self.graph_url.as_ref().is_some_and(|graph_url| !request.uri().to_string().starts_with(graph_url.as_str())) {
+            return next.run(request).await;
+        }
+        let method = request.method().to_string();
+        let url = request.uri().path_and_query().map_or("/", |p| p.as_str());
+        let url = redact_str(&urlencoding::decode(url).map_or_else(|_| url.to_string(), |u| u.into_owned()));
+
+        if self.mode == FixtureMode::Replay {
+            let recorded = self.find(&method, &url).map_err(MicrosoftError::into_protocol_error)?;
+            let mut res = Response::new(body_from_value(recorded.body).into());
+            *res.status_mut() = StatusCode::from_u16(recorded.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+            for (name, value) in recorded.headers {
+                if let (Ok(name), Ok(value)) = (name.parse::<header::HeaderName>(), value.parse()) {
+                    res.headers_mut().insert(name, value);
+                }
+            }
+            return Ok(res);
+        }
+
+        let form = request
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("application/x-www-form-urlencoded"));
+        let request_body = match form {
+            true => value_from_form(request.body()),
+            false => value_from_body(request.body()),
+        };
+        let res = next.run(request).await?;
+        let (parts, body) = res.into_parts();
+        let body = body.into_content_type(parts.headers.get(header::CONTENT_TYPE)).await?;
+        let headers = parts
+            .headers
+            .iter()
+            .filter(|(name, _)| KEPT_HEADERS.contains(&name.as_str()))
+            .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+            .collect();
+        let interaction = Interaction {
+            request: RecordedRequest {
+                method,
+                url,
+                body: redact(request_body),
+            },
+            response: RecordedResponse {
+                status: parts.status.as_u16(),
+                headers,
+                body: redact(value_from_body(&body)),
+            },
+        };
+        {
+            let mut interactions = self.interactions.lock().unwrap();
+            interactions.push(interaction);
+            self.save(&interactions).map_err(MicrosoftError::into_protocol_error)?;
+        }
+        Ok(Response::from_parts(parts, Body::InMemory(body)))
+    }
+}
+
+/// JSON bodies are kept as JSON, so fixtures stay readable and diffable.
+fn value_from_body(body: &InMemoryBody) -> Value {
+    match body {
+        InMemoryBody::Empty => Value::Null,
+        InMemoryBody::Json(value) => value.clone(),
+        InMemoryBody::Text(text) => serde_json::from_str(text).unwrap_or_else(|_| Value::String(text.clone())),
+        InMemoryBody::Bytes(bytes) => match serde_json::from_slice(bytes) {
+            Ok(value) => value,
+            Err(_) => Value::String(String::from_utf8_lossy(bytes).into_owned()),
+        },
+    }
+}
+
+/// Form bodies become an object of their fields, so secrets in them are redacted like JSON fields.
+fn value_from_form(body: &InMemoryBody) -> Value {
+    let text = match body {
+        InMemoryBody::Text(text) => text.clone(),
+        InMemoryBody::Bytes(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+        body => return value_from_body(body),
+    };
+    let decode = |s: &str| {
+        let s = s.replace('+', " ");
+        urlencoding::decode(&s).map_or_else(|_| s.clone(), |d| d.into_owned())
+    };
+    text.split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            (decode(key), Value::String(decode(value)))
+        })
+        .collect::<serde_json::Map<_, _>>()
+        .into()
+}
+
+fn body_from_value(value: Value) -> InMemoryBody {
+    match value {
+        Value::Null => InMemoryBody::Empty,
+        Value::String(text) => InMemoryBody::Text(text),
+        value => InMemoryBody::Text(value.to_string()),
+    }
+}
+
+fn redact(value: Value) -> Value {
+    redact_value(None, value)
+}
+
+fn redact_value(key: Option<&str>, value: Value) -> Value {
+    match value {
+        Value::String(_) if key.is_some_and(|k| SECRET_FIELDS.contains(&k) || NAME_FIELDS.contains(&k)) => {
+            Value::String(REDACTED.to_string())
+        }
+        Value::String(s) => Value::String(redact_str(&s)),
+        Value::Array(values) => Value::Array(values.into_iter().map(|v| redact_value(key, v)).collect()),
+        Value::Object(map) => {
+            let is_address = map.contains_key("address");
+            map.into_iter()
+                .map(|(k, v)| {
+                    // the `name` of an `emailAddress` is a person's
+                    let v = match (k.as_str(), v) {
+                        ("name", Value::String(_)) if is_address => Value::String(REDACTED.to_string()),
+                        (_, v) => redact_value(Some(&k), v),
+                    };
+                    (k, v)
+                })
+                .collect()
+        }
+        value => value,
+    }
+}
+
+/// Replace JWTs, and pseudonymize email addresses, anywhere in `s`.
+fn redact_str(s: &str) -> String {
+    if s.starts_with("eyJ") && s.matches('.').count() == 2 {
+        return REDACTED.to_string();
+    }
+    let is_local = |c: char| c.is_ascii_alphanumeric() || "._%+-'".contains(c);
+    let is_domain = |c: char| c.is_ascii_alphanumeric() || ".-".contains(c);
+    let mut out = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(at) = rest.find('@') {
+        let start = rest[..at].rfind(|c| !is_local(c)).map_or(0, |i| i + 1);
+        let end = rest[at + 1..].find(|c| !is_domain(c)).map_or(rest.len(), |i| at + 1 + i);
+        let domain = rest[at + 1..end].trim_end_matches('.');
+        let end = at + 1 + domain.len();
+        if start == at || !domain.contains('.') {
+            out.push_str(&rest[..=at]);
+            rest = &rest[at + 1..];
+            continue;
+        }
+        let address = rest[start..end].to_lowercase();
+        let hash = Sha256::digest(address.as_bytes());
+        out.push_str(&rest[..start]);
+        out.push_str(&format!("user-{:02x}{:02x}{:02x}{:02x}@example.com", hash[0], hash[1], hash[2], hash[3]));
+        rest = &rest[end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::{ClientCredentials, StaticToken};
+    use crate::mock::{MockGraph, MOCK_TOKEN};
+    use crate::test_util::serve;
+    use crate::{Cloud, MicrosoftAuth, MicrosoftClient};
+    use serde_json::json;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_redact() {
+        assert_eq!(redact_str("Alice@Contoso.com"), redact_str("alice@contoso.com"));
+        let redacted = redact_str("/users/alice@contoso.com/messages, from <bob@contoso.com>. @home");
+        assert!(redacted.starts_with("/users/user-"));
+        assert!(redacted.ends_with("@example.com>. @home"));
+        assert!(!redacted.contains("contoso"));
+        let value = redact(json!({"emailAddress": {"name": "Alice", "address": "alice@contoso.com"}, "refresh_token": "r"}));
+        assert_eq!(value["emailAddress"]["name"], REDACTED);
+        assert_eq!(value["refresh_token"], REDACTED);
+        let form = InMemoryBody::Text("client_id=app&client_secret=s3cr%2Bt&scope=https%3A%2F%2Fgraph.microsoft.com%2F.default".to_string());
+        let value = redact(value_from_form(&form));
+        assert_eq!(value, json!({"client_id": "app", "client_secret": REDACTED, "scope": "https://graph.microsoft.com/.default"}));
+    }
+
+    #[tokio::test]
+    async fn test_record_replay() {
+        let path = std::env::temp_dir().join(format!("microsoft_mail_fixture_{}.json", uuid::Uuid::new_v4()));
+        let graph = MockGraph::start().await;
+        graph.insert_message(json!({"subject": "Hello", "from": {"emailAddress": {"name": "Alice", "address": "alice@contoso.com"}}}));
+        let recording = MicrosoftClient::builder()
+            .http_client(httpclient::Client::new())
+            .base_url(graph.url())
+            .auth(MicrosoftAuth::provider(StaticToken::new(MOCK_TOKEN)))
+            .fixture(Fixture::record(&path))
+            .build()
+            .unwrap();
+        let recorded = recording.list_messages().await.unwrap();
+        recording.list_messages().await.unwrap();
+        let err = recording.get_message("missing").await.unwrap_err();
+        let saved = fs::read_to_string(&path).unwrap();
+        assert!(!saved.contains("alice@contoso.com") && !saved.contains(MOCK_TOKEN));
+
+        // nothing is listening on this port, so the test fails if a request is sent
+        let replaying = MicrosoftClient::builder()
+            .http_client(httpclient::Client::new())
+            .base_url("http://127.0.0.1:9/v1.0")
+            .auth(MicrosoftAuth::provider(StaticToken::new("replay")))
+            .fixture(Fixture::replay(&path).unwrap())
+            .build()
+            .unwrap();
+        for _ in 0..2 {
+            let page = replaying.list_messages().await.unwrap();
+            assert_eq!(page[0].subject, recorded[0].subject);
+            assert!(page[0].from.as_ref().unwrap().email_address.address.ends_with("@example.com"));
+        }
+        let replayed = replaying.get_message("missing").await.unwrap_err();
+        assert_eq!(replayed.status(), err.status());
+        // every recorded response has been used
+        let Err(err) = replaying.list_messages().await else {
+            panic!("expected the fixture to be exhausted")
+        };
+        assert!(matches!(err, MicrosoftError::Config(_)), "{err}");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_record_skips_token_requests() {
+        let path = std::env::temp_dir().join(format!("microsoft_mail_fixture_{}.json", uuid::Uuid::new_v4()));
+        let graph = MockGraph::start().await;
+        graph.insert_message(json!({"subject": "Hello"}));
+        let token_url = serve(|_req| async move {
+            (200, format!(r#"{{"token_type":"Bearer","expires_in":3599,"access_token":"{MOCK_TOKEN}"}}"#))
+        })
+        .await;
+        let auth = ClientCredentials::new(Cloud::Global, "t", "app", "app-secret").token_endpoint(format!("{token_url}/token"));
+        let recording = MicrosoftClient::builder()
+            .http_client(httpclient::Client::new())
+            .base_url(graph.url())
+            .auth(MicrosoftAuth::ClientCredentials {
+                middleware: Arc::new(auth),
+            })
+            .fixture(Fixture::record(&path))
+            .build()
+            .unwrap();
+        recording.list_messages().mailbox("alice@contoso.com").await.unwrap();
+        let saved = fs::read_to_string(&path).unwrap();
+        assert!(!saved.contains("app-secret") && !saved.contains("/token"), "{saved}");
+        let file: FixtureFile = serde_json::from_str(&saved).unwrap();
+        assert_eq!(file.interactions.len(), 1);
+        fs::remove_file(&path).unwrap();
+    }
+}
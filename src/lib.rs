@@ -1,9 +1,30 @@
+pub mod auth;
+mod builder;
+pub mod cloud;
+pub mod encryption;
+pub mod error;
+pub mod fixture;
+#[cfg(any(test, feature = "test-support"))]
+pub mod mock;
 pub mod model;
 pub mod request;
+pub mod retry;
+pub mod subscription_manager;
+#[cfg(test)]
+mod test_util;
+mod timeout;
+#[cfg(feature = "webhook")]
+pub mod webhook;
 
+pub use crate::auth::MicrosoftAuth;
+pub use crate::builder::MicrosoftClientBuilder;
+pub use crate::cloud::{ApiVersion, Cloud};
+pub use crate::error::{GraphError, MicrosoftError, MicrosoftResult};
+use crate::fixture::Fixture;
 use crate::model::User;
-use httpclient::{InMemoryResponseExt, InMemoryResult, RequestBuilder};
-use httpclient_oauth2::RefreshData;
+use crate::retry::RetryPolicy;
+use crate::timeout::Timeout;
+use httpclient::{InMemoryResponseExt, RequestBuilder};
 use std::borrow::Cow;
 use std::sync::{Arc, OnceLock};
 // pub use odata_params::filters::*;
@@ -19,18 +40,21 @@ pub fn init_oauth2_flow(init: httpclient_oauth2::OAuth2Flow) {
 pub fn default_http_client() -> httpclient::Client {
     httpclient::Client::new()
         .no_default_headers()
-        .base_url("https://graph.microsoft.com/v1.0")
+        .base_url(&Cloud::Global.graph_url(ApiVersion::V1))
 }
 
+pub(crate) fn try_shared_oauth2_flow() -> MicrosoftResult<&'static httpclient_oauth2::OAuth2Flow> {
+    if let Some(flow) = SHARED_OAUTH2FLOW.get() {
+        return Ok(flow);
+    }
+    let flow = Cloud::Global.oauth2_flow_from_env()?;
+    Ok(SHARED_OAUTH2FLOW.get_or_init(|| flow))
+}
+
+/// The flow set with [`init_oauth2_flow`], or read from the environment.
+/// Panics if neither is available. Use [`MicrosoftClient::builder`] to configure the flow per client instead.
 pub fn shared_oauth2_flow() -> &'static httpclient_oauth2::OAuth2Flow {
-    SHARED_OAUTH2FLOW.get_or_init(|| httpclient_oauth2::OAuth2Flow {
-        client_id: std::env::var("MICROSOFT_CLIENT_ID").expect("MICROSOFT_CLIENT_ID must be set"),
-        client_secret: std::env::var("MICROSOFT_CLIENT_SECRET").expect("MICROSOFT_CLIENT_SECRET must be set"),
-        init_endpoint: "https://login.microsoftonline.com/common/oauth2/v2.0/authorize".to_string(),
-        exchange_endpoint: "https://login.microsoftonline.com/common/oauth2/v2.0/token".to_string(),
-        refresh_endpoint: "https://login.microsoftonline.com/common/oauth2/v2.0/token".to_string(),
-        redirect_uri: std::env::var("MICROSOFT_REDIRECT_URI").expect("MICROSOFT_REDIRECT_URI must be set"),
-    })
+    try_shared_oauth2_flow().unwrap_or_else(|e| panic!("{e}"))
 }
 
 pub fn init_http_client(init: httpclient::Client) {
@@ -44,30 +68,38 @@ pub fn shared_http_client() -> Cow<'static, httpclient::Client> {
 #[derive(Clone)]
 pub struct FluentRequest<'a, T> {
     pub(crate) client: &'a MicrosoftClient,
+    pub(crate) options: RequestOptions,
     pub params: T,
 }
 
-pub enum MicrosoftAuth {
-    OAuth2 { middleware: Arc<httpclient_oauth2::OAuth2> },
+/// Per-request overrides of the client configuration.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct RequestOptions {
+    pub retry: Option<Arc<RetryPolicy>>,
+    pub api_version: Option<ApiVersion>,
 }
 
-impl MicrosoftAuth {
-    pub fn oauth2(
-        access: impl Into<String>, refresh: impl Into<String>, callback: Option<Box<dyn Fn(RefreshData) + Send + Sync + 'static>>,
-    ) -> Self {
-        let mut mw = shared_oauth2_flow().bearer_middleware(access.into(), refresh.into());
-        if let Some(cb) = callback {
-            mw.callback(cb);
-        }
-        Self::OAuth2 {
-            middleware: Arc::new(mw),
-        }
+impl<T> FluentRequest<'_, T> {
+    /// Use a different [`RetryPolicy`] than the client's for this request only.
+    pub fn retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.options.retry = Some(Arc::new(policy));
+        self
+    }
+
+    /// Send this request to another api version than the client's, e.g. for endpoints that only exist in beta.
+    pub fn api_version(mut self, version: ApiVersion) -> Self {
+        self.options.api_version = Some(version);
+        self
     }
 }
 
 pub struct MicrosoftClient {
     client: Cow<'static, httpclient::Client>,
     authentication: MicrosoftAuth,
+    retry: Arc<RetryPolicy>,
+    timeout: Option<Arc<Timeout>>,
+    preflight: bool,
+    fixture: Option<Arc<Fixture>>,
 }
 
 impl MicrosoftClient {
@@ -75,21 +107,44 @@ impl MicrosoftClient {
         Self {
             client: shared_http_client(),
             authentication: auth,
+            retry: Arc::new(RetryPolicy::default()),
+            timeout: None,
+            preflight: false,
+            fixture: None,
         }
     }
 
-    pub async fn me(&self) -> InMemoryResult<User> {
+    pub fn builder() -> MicrosoftClientBuilder {
+        MicrosoftClientBuilder::default()
+    }
+
+    /// Replace the default [`RetryPolicy`], which applies to every request made through this client.
+    pub fn with_retry_policy(mut self, policy: RetryPolicy) -> Self {
+        self.retry = Arc::new(policy);
+        self
+    }
+
+    pub async fn me(&self) -> MicrosoftResult<User> {
         let mut r = self.client.get("/me");
-        r = self.authorize(r);
-        r.await?.json().map_err(Into::into)
+        r = self.authorize(r, &RequestOptions::default());
+        Ok(r.await?.json()?)
     }
 
-    fn authorize<'a>(&self, mut req: RequestBuilder<'a>) -> RequestBuilder<'a> {
-        match &self.authentication {
-            MicrosoftAuth::OAuth2 { middleware } => {
-                req.middlewares.insert(0, middleware.clone());
-            }
+    fn authorize<'a>(&self, mut req: RequestBuilder<'a>, options: &RequestOptions) -> RequestBuilder<'a> {
+        if let Some(version) = options.api_version {
+            req.uri = version.rewrite(&req.uri);
+        }
+        // innermost, so every attempt is recorded with the headers auth set, and replayed in the same order
+        if let Some(fixture) = &self.fixture {
+            req.middlewares.insert(0, fixture.clone());
+        }
+        // inside retry and auth, so the timeout applies to each attempt
+        if let Some(timeout) = &self.timeout {
+            req.middlewares.insert(0, timeout.clone());
         }
+        req.middlewares.insert(0, self.authentication.middleware());
+        // outermost, so every retry goes through auth again
+        req.middlewares.insert(0, options.retry.as_ref().unwrap_or(&self.retry).clone());
         // see https://learn.microsoft.com/en-us/graph/outlook-immutable-id
         req = req.header("Prefer", r#"IdType="ImmutableId""#);
         req
@@ -97,4 +152,149 @@ impl MicrosoftClient {
 }
 
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+    use crate::mock::{Failure, MockGraph, MOCK_USER};
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_paging() {
+        let graph = MockGraph::start().await;
+        for subject in ["one", "two", "three"] {
+            graph.insert_message(json!({ "subject": subject }));
+        }
+        let client = graph.client();
+        let page = client.list_messages().top(2).await.unwrap();
+        assert_eq!(page.iter().map(|m| m.subject.as_str()).collect::<Vec<_>>(), ["one", "two"]);
+        let page = client.list_messages().next(page.next_link.clone().unwrap()).await.unwrap();
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].subject, "three");
+        assert!(page.next_link.is_none());
+
+        // the selected fields are all that comes back
+        let page = client.list_messages().select(vec!["subject".to_string()]).top(2).await.unwrap();
+        assert_eq!(page[1].subject, "two");
+        assert!(page[1].web_link.is_empty());
+        assert!(page.next_link.unwrap().contains("$select=subject"));
+    }
+
+    #[tokio::test]
+    async fn test_api_version() {
+        let graph = MockGraph::start().await;
+        for subject in ["one", "two", "three"] {
+            graph.insert_message(json!({ "subject": subject }));
+        }
+        let client = graph.client();
+        let page = client.list_messages().top(2).api_version(ApiVersion::Beta).await.unwrap();
+        client.me().await.unwrap();
+        // the next link is absolute, and still points at v1.0
+        let next_link = page.next_link.clone().unwrap();
+        assert!(next_link.starts_with("http://") && next_link.contains("/v1.0/"), "{next_link}");
+        let page = client.list_messages().next(next_link).api_version(ApiVersion::Beta).await.unwrap();
+        assert_eq!(page[0].subject, "three");
+        client.list_messages().await.unwrap();
+
+        let requests = graph.requests();
+        let versions: Vec<_> = requests.iter().map(|r| (r.path.as_str(), r.api_version)).collect();
+        assert_eq!(
+            versions,
+            [
+                ("/me/messages", Some(ApiVersion::Beta)),
+                ("/me", Some(ApiVersion::V1)),
+                ("/me/messages", Some(ApiVersion::Beta)),
+                ("/me/messages", Some(ApiVersion::V1)),
+            ]
+        );
+        assert!(requests[2].query.as_deref().is_some_and(|q| q.contains("$skip=2")), "{:?}", requests[2].query);
+    }
+
+    #[tokio::test]
+    async fn test_reply() {
+        let graph = MockGraph::start().await;
+        let id = graph.insert_message(json!({
+            "subject": "Lunch?",
+            "from": {"emailAddress": {"address": "bob@contoso.com"}},
+            "body": {"contentType": "html", "content": "<p>Noon?</p>"},
+        }));
+        let email = email::Email {
+            from: MOCK_USER.into(),
+            to: vec!["bob@contoso.com".into()],
+            cc: vec![],
+            bcc: vec![],
+            subject: String::new(),
+            body: email::Body::Text("Sure".to_string()),
+            attachments: vec![],
+            reply_to_message_id: Some(id),
+            thread_id: None,
+        };
+        graph.client().send_email(email).await.unwrap();
+        let sent = graph.sent();
+        assert_eq!(sent.len(), 1);
+        assert_eq!(sent[0]["subject"], "RE: Lunch?");
+        assert_eq!(sent[0]["body"]["content"], "<html><body>Sure<hr><p>Noon?</p></body></html>");
+    }
+
+    #[tokio::test]
+    async fn test_errors() {
+        let graph = MockGraph::start().await;
+        let client = graph.client();
+        let err = client.get_message("missing").await.unwrap_err();
+        assert!(matches!(err, MicrosoftError::NotFound(_)), "{err}");
+        assert!(err.request_id().is_some());
+
+        // throttling is retried
+        graph.inject(Failure::throttle(0).path("/me/messages").times(2));
+        client.list_messages().await.unwrap();
+        assert_eq!(graph.requests().iter().filter(|r| r.path == "/me/messages").count(), 3);
+
+        graph.inject(Failure::new(404, "MailboxNotEnabledForRESTAPI"));
+        let err = client.me().await.unwrap_err();
+        assert!(matches!(err, MicrosoftError::MailboxNotEnabled(_)), "{err}");
+
+        graph.inject(Failure::new(500, "InternalServerError").times(0));
+        client.me().await.unwrap();
+        client.me().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_batch() {
+        let graph = MockGraph::start().await;
+        let id = graph.insert_message(json!({"subject": "Hello", "isRead": false}));
+        graph.inject(Failure::throttle(7).path(format!("/me/messages/{id}/attachments")));
+        let client = graph.client();
+
+        let mut batch = client.batch();
+        let found = batch.add(client.get_message(&id));
+        let missing = batch.add(client.get_message("missing"));
+        let skipped = batch.add_after(client.update_message("missing").is_read(true), &missing);
+        let throttled = batch.add(client.list_attachments(&id));
+        let res = batch.await.unwrap();
+        assert_eq!(res.get(&found).unwrap().subject, "Hello");
+        let err = res.get(&missing).unwrap_err();
+        assert!(matches!(err, MicrosoftError::NotFound(_)), "{err}");
+        assert!(err.request_id().is_some());
+        let err = res.get(&skipped).unwrap_err();
+        assert_eq!(err.status(), Some(httpclient::StatusCode::FAILED_DEPENDENCY));
+        let err = res.get(&throttled).unwrap_err();
+        assert!(matches!(err, MicrosoftError::Throttled { retry_after: Some(d), .. } if d.as_secs() == 7), "{err}");
+        // a handle from another batch has no response in this one
+        let mut other = client.batch();
+        for _ in 0..4 {
+            other.add(client.get_message(&id));
+        }
+        let stray = other.add(client.get_message(&id));
+        assert!(matches!(res.get(&stray), Err(MicrosoftError::UnexpectedResponse(_))));
+
+        // more than 20 requests are split, keeping the update in the same batch as the read it depends on
+        let mut batch = client.batch();
+        for _ in 0..19 {
+            batch.add(client.get_message(&id));
+        }
+        let read = batch.add(client.get_message(&id));
+        let update = batch.add_after(client.update_message(&id).is_read(true), &read);
+        let res = batch.await.unwrap();
+        assert_eq!(res.status(&update), Some(httpclient::StatusCode::OK));
+        assert!(res.get(&update).unwrap().is_read);
+        assert_eq!(graph.requests().iter().filter(|r| r.path == "/$batch").count(), 3);
+    }
+}
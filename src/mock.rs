@@ -0,0 +1,645 @@
+//! An in-process imitation of the Graph mail endpoints this crate uses, for tests that can't reach the real Graph.
+//! Enable the `test-support` feature to use it outside this crate.
+//!
+//! ```no_run
+//! # async fn example() {
+//! use microsoft_mail::mock::{Failure, MockGraph};
+//! use serde_json::json;
+//!
+//! let graph = MockGraph::start().await;
+//! graph.insert_message(json!({"subject": "Hello"}));
+//! graph.inject(Failure::throttle(0).path("/me/messages"));
+//! let page = graph.client().list_messages().await.unwrap();
+//! assert_eq!(page[0].subject, "Hello");
+//! # }
+//! ```
+//!
+//! Every user shares one mailbox, and `$filter`, `$orderby` and `$search` are ignored. Messages are listed in the
+//! order they were inserted, `$top` at a time; `$select` is honored. `$batch` requests are answered item by item.
+use crate::auth::StaticToken;
+use crate::{ApiVersion, MicrosoftAuth, MicrosoftClient};
+use chrono::{SecondsFormat, Utc};
+use http_body_util::BodyExt;
+use httpclient::Method;
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response};
+use hyper_util::rt::TokioIo;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex, MutexGuard};
+
+/// The signed in user of the mock. `/me` and every `/users/{id}` resolve to them.
+pub const MOCK_USER: &str = "adele@contoso.com";
+
+/// The access token of [`MockGraph::client`]. Requests without a bearer token are answered with a 401.
+pub const MOCK_TOKEN: &str = "mock-access-token";
+
+const DEFAULT_PAGE_SIZE: usize = 10;
+
+/// A Graph server on a random local port. It stops when the tokio runtime it was started on shuts down.
+#[derive(Clone)]
+pub struct MockGraph {
+    url: String,
+    state: Arc<Mutex<State>>,
+}
+
+#[derive(Default)]
+struct State {
+    messages: Vec<Value>,
+    attachments: HashMap<String, Vec<Value>>,
+    sent: Vec<String>,
+    failures: Vec<Failure>,
+    requests: Vec<RecordedRequest>,
+    page_size: Option<usize>,
+    next_id: u64,
+}
+
+impl State {
+    fn next_id(&mut self, prefix: &str) -> String {
+        self.next_id += 1;
+        format!("{prefix}{:06}", self.next_id)
+    }
+
+    fn message(&mut self, id: &str) -> Option<&mut Value> {
+        self.messages.iter_mut().find(|m| m["id"] == id)
+    }
+}
+
+/// A request the mock received, for assertions about what the client sent.
+#[derive(Debug, Clone)]
+pub struct RecordedRequest {
+    pub method: Method,
+    /// Without the api version, e.g. `/me/messages`.
+    pub path: String,
+    pub query: Option<String>,
+    pub body: Option<Value>,
+    /// The version segment the url started with, if any. Requests inside a `$batch` have the version of the batch.
+    pub api_version: Option<ApiVersion>,
+}
+
+/// An error response to answer matching requests with, instead of handling them.
+#[derive(Debug, Clone)]
+pub struct Failure {
+    method: Option<Method>,
+    path: Option<String>,
+    status: u16,
+    code: String,
+    message: String,
+    retry_after: Option<u64>,
+    times: usize,
+}
+
+impl Failure {
+    /// Fails the next request, whatever it is, once. `code` is the Graph error code, e.g. `ErrorItemNotFound`.
+    pub fn new(status: u16, code: impl Into<String>) -> Self {
+        Self {
+            method: None,
+            path: None,
+            status,
+            code: code.into(),
+            message: "Injected by MockGraph".to_string(),
+            retry_after: None,
+            times: 1,
+        }
+    }
+
+    /// A 429 with a `Retry-After` of `retry_after` seconds.
+    pub fn throttle(retry_after: u64) -> Self {
+        Self::new(429, "TooManyRequests").retry_after(retry_after)
+    }
+
+    pub fn method(mut self, method: Method) -> Self {
+        self.method = Some(method);
+        self
+    }
+
+    /// Only fail requests whose path, without the api version, starts with `path`.
+    pub fn path(mut self, path: impl Into<String>) -> Self {
+        self.path = Some(path.into());
+        self
+    }
+
+    pub fn message(mut self, message: impl Into<String>) -> Self {
+        self.message = message.into();
+        self
+    }
+
+    pub fn retry_after(mut self, seconds: u64) -> Self {
+        self.retry_after = Some(seconds);
+        self
+    }
+
+    /// Fail this many matching requests before handling them again. `0` never fails.
+    pub fn times(mut self, times: usize) -> Self {
+        self.times = times;
+        self
+    }
+
+    fn matches(&self, method: &Method, path: &str) -> bool {
+        self.method.as_ref().is_none_or(|m| m == method) && self.path.as_ref().is_none_or(|p| path.starts_with(p.as_str()))
+    }
+}
+
+struct MockResponse {
+    status: u16,
+    body: Option<Value>,
+    retry_after: Option<u64>,
+}
+
+impl MockResponse {
+    fn ok(body: Value) -> Self {
+        Self::with_status(200, body)
+    }
+
+    fn with_status(status: u16, body: Value) -> Self {
+        Self {
+            status,
+            body: Some(body),
+            retry_after: None,
+        }
+    }
+
+    fn accepted() -> Self {
+        Self {
+            status: 202,
+            body: None,
+            retry_after: None,
+        }
+    }
+
+    fn error(status: u16, code: &str, message: &str) -> Self {
+        let body = json!({"error": {
+            "code": code,
+            "message": message,
+            "innerError": {
+                "request-id": uuid::Uuid::new_v4().to_string(),
+                "date": Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true),
+            },
+        }});
+        Self::with_status(status, body)
+    }
+
+    fn not_found(id: &str) -> Self {
+        Self::error(404, "ErrorItemNotFound", &format!("The specified object {id} was not found in the store."))
+    }
+}
+
+impl MockGraph {
+    /// Start serving an empty mailbox.
+    pub async fn start() -> Self {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("Failed to bind the mock server");
+        let addr = listener.local_addr().unwrap();
+        let graph = Self {
+            url: format!("http://{addr}/v1.0"),
+            state: Arc::default(),
+        };
+        let server = graph.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let server = server.clone();
+                tokio::spawn(async move {
+                    let svc = service_fn(move |req| {
+                        let server = server.clone();
+                        async move { Ok::<_, Infallible>(server.respond(req).await) }
+                    });
+                    let _ = http1::Builder::new().serve_connection(TokioIo::new(stream), svc).await;
+                });
+            }
+        });
+        graph
+    }
+
+    /// The base url, including the api version, e.g. `http://127.0.0.1:1234/v1.0`.
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// A client signed in as [`MOCK_USER`].
+    pub fn client(&self) -> MicrosoftClient {
+        MicrosoftClient::builder()
+            .http_client(httpclient::Client::new())
+            .base_url(&self.url)
+            .auth(MicrosoftAuth::provider(StaticToken::new(MOCK_TOKEN)))
+            .build()
+            .expect("Failed to build the mock client")
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    /// How many messages a list returns without `$top`. Graph's default is 10.
+    pub fn page_size(&self, page_size: usize) {
+        self.state().page_size = Some(page_size);
+    }
+
+    /// Add a message to the inbox and return its id. Fields missing from `message` are filled in, so the
+    /// message always deserializes as a [`crate::model::EmailMessage`].
+    pub fn insert_message(&self, message: Value) -> String {
+        let mut state = self.state();
+        let id = state.next_id("AAMkAGI2");
+        let mut full = message_template(&id, "inbox");
+        merge(&mut full, message);
+        full["id"] = json!(id);
+        state.messages.push(full);
+        id
+    }
+
+    /// Add a file attachment to a message and return its id.
+    pub fn insert_attachment(&self, message_id: &str, name: &str, content_type: &str, content: &[u8]) -> String {
+        use base64::Engine;
+        let mut state = self.state();
+        let id = state.next_id("AAMkAGI2att");
+        let attachment = json!({
+            "@odata.type": "#microsoft.graph.fileAttachment",
+            "@odata.mediaContentType": content_type,
+            "contentBytes": base64::engine::general_purpose::STANDARD.encode(content),
+            "contentType": content_type,
+            "name": name,
+        });
+        add_attachment(&mut state, message_id, &id, attachment);
+        id
+    }
+
+    /// Answer matching requests with `failure` until it has been used up. Failures are tried in the order
+    /// they were injected.
+    pub fn inject(&self, failure: Failure) {
+        if failure.times > 0 {
+            self.state().failures.push(failure);
+        }
+    }
+
+    /// Every message in the mailbox, drafts and sent mail included.
+    pub fn messages(&self) -> Vec<Value> {
+        self.state().messages.clone()
+    }
+
+    pub fn message(&self, id: &str) -> Option<Value> {
+        self.state().message(id).cloned()
+    }
+
+    /// The messages sent with `/send`, in the order they were sent.
+    pub fn sent(&self) -> Vec<Value> {
+        let mut state = self.state();
+        let ids = state.sent.clone();
+        ids.iter().filter_map(|id| state.message(id).cloned()).collect()
+    }
+
+    pub fn attachments(&self, message_id: &str) -> Vec<Value> {
+        self.state().attachments.get(message_id).cloned().unwrap_or_default()
+    }
+
+    /// Every request received so far, including failed ones.
+    pub fn requests(&self) -> Vec<RecordedRequest> {
+        self.state().requests.clone()
+    }
+
+    async fn respond(&self, req: Request<Incoming>) -> Response<String> {
+        let (parts, body) = req.into_parts();
+        let body = body.collect().await.map(|b| b.to_bytes()).unwrap_or_default();
+        let body = serde_json::from_slice::<Value>(&body).ok();
+        let method: Method = parts.method.as_str().parse().unwrap();
+        let path = parts.uri.path();
+        // the version segment is optional, so clients configured without one work too
+        let (api_version, path) = [ApiVersion::V1, ApiVersion::Beta]
+            .into_iter()
+            .find_map(|v| path.strip_prefix('/').and_then(|p| p.strip_prefix(v.as_str())).map(|p| (Some(v), p)))
+            .unwrap_or((None, path));
+        let path = path.to_string();
+        let query = parts.uri.query().map(str::to_string);
+        let authorized = parts
+            .headers
+            .get("authorization")
+            .and_then(|v| v.to_str().ok())
+            .is_some_and(|v| v.starts_with("Bearer "));
+
+        let request = RecordedRequest { method, path, query, body, api_version };
+        let res = self.handle(&mut self.state(), request, authorized);
+
+        let mut builder = Response::builder().status(res.status);
+        if let Some(seconds) = res.retry_after {
+            builder = builder.header("Retry-After", seconds.to_string());
+        }
+        let body = match res.body {
+            Some(body) => {
+                builder = builder.header("Content-Type", "application/json");
+                body.to_string()
+            }
+            None => String::new(),
+        };
+        builder.body(body).unwrap()
+    }
+
+    fn handle(&self, state: &mut State, request: RecordedRequest, authorized: bool) -> MockResponse {
+        state.requests.push(request.clone());
+        let RecordedRequest { method, path, query, body, api_version } = request;
+        let failure = state.failures.iter().position(|f| f.matches(&method, &path));
+        if let Some(i) = failure {
+            let failure = state.failures[i].clone();
+            state.failures[i].times -= 1;
+            if state.failures[i].times == 0 {
+                state.failures.remove(i);
+            }
+            let mut res = MockResponse::error(failure.status, &failure.code, &failure.message);
+            res.retry_after = failure.retry_after;
+            res
+        } else if !authorized {
+            MockResponse::error(401, "InvalidAuthenticationToken", "Access token is empty.")
+        } else if method == Method::POST && path == "/$batch" {
+            self.batch(state, body.unwrap_or_default(), api_version)
+        } else {
+            let query = parse_query(query.as_deref());
+            self.route(state, &method, &path, &query, body)
+        }
+    }
+
+    /// Answers each request of a `$batch` with the other routes, in order. Requests whose `dependsOn` failed get a 424.
+    fn batch(&self, state: &mut State, body: Value, api_version: Option<ApiVersion>) -> MockResponse {
+        let mut statuses: HashMap<String, u16> = HashMap::new();
+        let mut responses = Vec::new();
+        for request in body["requests"].as_array().cloned().unwrap_or_default() {
+            let id = request["id"].as_str().unwrap_or_default().to_string();
+            let failed_dependency = request["dependsOn"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .any(|d| d.as_str().and_then(|d| statuses.get(d)).is_none_or(|status| *status >= 400));
+            let res = if failed_dependency {
+                MockResponse::error(424, "FailedDependency", "A dependent request failed.")
+            } else {
+                let method = request["method"].as_str().unwrap_or_default().parse().unwrap_or(Method::GET);
+                let url = request["url"].as_str().unwrap_or_default();
+                let (path, query) = url.split_once('?').map_or((url, None), |(path, query)| (path, Some(query.to_string())));
+                let body = Some(request["body"].clone()).filter(|b| !b.is_null());
+                let request = RecordedRequest { method, path: path.to_string(), query, body, api_version };
+                self.handle(state, request, true)
+            };
+            statuses.insert(id.clone(), res.status);
+            let mut headers = Map::new();
+            headers.insert("request-id".to_string(), json!(uuid::Uuid::new_v4().to_string()));
+            if let Some(seconds) = res.retry_after {
+                headers.insert("Retry-After".to_string(), json!(seconds.to_string()));
+            }
+            let mut response = json!({"id": id, "status": res.status, "headers": headers});
+            if let Some(body) = res.body {
+                response["body"] = body;
+            }
+            responses.push(response);
+        }
+        MockResponse::ok(json!({ "responses": responses }))
+    }
+
+    fn route(
+        &self, state: &mut State, method: &Method, path: &str, query: &HashMap<String, String>, body: Option<Value>,
+    ) -> MockResponse {
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        let (owner, rest) = match segments.as_slice() {
+            ["me", rest @ ..] => ("/me".to_string(), rest),
+            ["users", user, rest @ ..] => (format!("/users/{user}"), rest),
+            _ => return unknown_segment(path),
+        };
+        let body = body.unwrap_or_else(|| json!({}));
+        match (method.as_str(), rest) {
+            ("GET", []) => MockResponse::ok(user()),
+            ("GET", ["mailFolders", folder]) => MockResponse::ok(json!({"id": folder, "displayName": folder})),
+            ("GET", ["messages"]) => self.list(state, &format!("{owner}/messages"), None, query),
+            ("GET", ["mailFolders", folder, "messages"]) => {
+                self.list(state, &format!("{owner}/mailFolders/{folder}/messages"), Some(folder), query)
+            }
+            ("POST", ["messages"]) => {
+                let id = state.next_id("AAMkAGI2");
+                let mut message = message_template(&id, "drafts");
+                message["isDraft"] = json!(true);
+                let mut body = body;
+                let attachments = body.as_object_mut().and_then(|b| b.remove("attachments"));
+                merge(&mut message, body);
+                state.messages.push(message.clone());
+                for attachment in attachments.and_then(|a| a.as_array().cloned()).unwrap_or_default() {
+                    let attachment_id = state.next_id("AAMkAGI2att");
+                    add_attachment(state, &id, &attachment_id, attachment);
+                }
+                MockResponse::with_status(201, state.message(&id).cloned().unwrap())
+            }
+            ("GET", ["messages", id]) => match state.message(id) {
+                Some(message) => MockResponse::ok(select(message, query)),
+                None => MockResponse::not_found(id),
+            },
+            ("PATCH", ["messages", id]) => match state.message(id) {
+                Some(message) => {
+                    merge(message, body);
+                    message["lastModifiedDateTime"] = json!(now());
+                    MockResponse::ok(message.clone())
+                }
+                None => MockResponse::not_found(id),
+            },
+            ("DELETE", ["messages", id]) => {
+                let before = state.messages.len();
+                state.messages.retain(|m| m["id"] != *id);
+                if state.messages.len() == before {
+                    return MockResponse::not_found(id);
+                }
+                MockResponse {
+                    status: 204,
+                    body: None,
+                    retry_after: None,
+                }
+            }
+            ("POST", ["messages", id, "send"]) => match state.message(id) {
+                Some(message) => {
+                    message["isDraft"] = json!(false);
+                    message["parentFolderId"] = json!("sentitems");
+                    message["sentDateTime"] = json!(now());
+                    state.sent.push(id.to_string());
+                    MockResponse::accepted()
+                }
+                None => MockResponse::not_found(id),
+            },
+            ("POST", ["messages", id, "createReply"]) => {
+                let Some(original) = state.message(id).cloned() else {
+                    return MockResponse::not_found(id);
+                };
+                let reply_id = state.next_id("AAMkAGI2");
+                let mut reply = message_template(&reply_id, "drafts");
+                let quoted = original["body"]["content"].as_str().unwrap_or_default();
+                merge(
+                    &mut reply,
+                    json!({
+                        "isDraft": true,
+                        "subject": format!("RE: {}", original["subject"].as_str().unwrap_or_default()),
+                        "conversationId": original["conversationId"],
+                        "toRecipients": original["from"].as_object().map(|from| vec![from.clone()]).unwrap_or_default(),
+                        "body": {"contentType": "html", "content": format!("<html><body><hr>{quoted}</body></html>")},
+                    }),
+                );
+                state.messages.push(reply.clone());
+                MockResponse::with_status(201, reply)
+            }
+            ("GET", ["messages", id, "attachments"]) => {
+                if state.message(id).is_none() {
+                    return MockResponse::not_found(id);
+                }
+                let attachments = state.attachments.get(*id).cloned().unwrap_or_default();
+                self.page(&format!("{owner}/messages/{id}/attachments"), attachments, state.page_size, query)
+            }
+            ("POST", ["messages", id, "attachments"]) => {
+                if state.message(id).is_none() {
+                    return MockResponse::not_found(id);
+                }
+                let attachment_id = state.next_id("AAMkAGI2att");
+                add_attachment(state, id, &attachment_id, body);
+                let attachment = state.attachments[*id].last().cloned().unwrap();
+                MockResponse::with_status(201, attachment)
+            }
+            _ => unknown_segment(path),
+        }
+    }
+
+    fn list(
+        &self, state: &State, path: &str, folder: Option<&str>, query: &HashMap<String, String>,
+    ) -> MockResponse {
+        let messages = state
+            .messages
+            .iter()
+            .filter(|m| folder.is_none_or(|f| m["parentFolderId"] == f))
+            .map(|m| select(m, query))
+            .collect();
+        self.page(path, messages, state.page_size, query)
+    }
+
+    /// One page of `items`, with an `@odata.nextLink` if there are more.
+    fn page(&self, path: &str, items: Vec<Value>, page_size: Option<usize>, query: &HashMap<String, String>) -> MockResponse {
+        let top = query.get("$top").and_then(|t| t.parse().ok()).or(page_size).unwrap_or(DEFAULT_PAGE_SIZE);
+        let skip = query.get("$skip").and_then(|s| s.parse().ok()).unwrap_or(0);
+        let value: Vec<Value> = items.iter().skip(skip).take(top).cloned().collect();
+        let mut page = json!({
+            "@odata.context": format!("{}/$metadata#{}", self.url, path.trim_start_matches('/')),
+            "value": value,
+        });
+        if skip + top < items.len() {
+            let mut next = vec![format!("$top={top}"), format!("$skip={}", skip + top)];
+            if let Some(select) = query.get("$select") {
+                next.push(format!("$select={}", urlencoding::encode(select)));
+            }
+            page["@odata.nextLink"] = json!(format!("{}{path}?{}", self.url, next.join("&")));
+        }
+        MockResponse::ok(page)
+    }
+}
+
+fn unknown_segment(path: &str) -> MockResponse {
+    MockResponse::error(400, "BadRequest", &format!("Resource not found for the segment '{path}'."))
+}
+
+fn now() -> String {
+    Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true)
+}
+
+fn user() -> Value {
+    json!({
+        "@odata.context": "https://graph.microsoft.com/v1.0/$metadata#users/$entity",
+        "businessPhones": [],
+        "displayName": "Adele Vance",
+        "givenName": "Adele",
+        "surname": "Vance",
+        "id": "87d349ed-44d7-43e1-9a83-5f2406dee5bd",
+        "mail": MOCK_USER,
+        "userPrincipalName": MOCK_USER,
+    })
+}
+
+fn recipient(address: &str) -> Value {
+    json!({"emailAddress": {"name": address, "address": address}})
+}
+
+/// A message with every field Graph returns without `$select`.
+fn message_template(id: &str, folder: &str) -> Value {
+    let now = now();
+    json!({
+        "@odata.etag": format!("W/\"{id}\""),
+        "id": id,
+        "createdDateTime": now,
+        "lastModifiedDateTime": now,
+        "changeKey": id,
+        "categories": [],
+        "receivedDateTime": now,
+        "sentDateTime": now,
+        "hasAttachments": false,
+        "internetMessageId": format!("<{id}@contoso.com>"),
+        "subject": "",
+        "bodyPreview": "",
+        "importance": "normal",
+        "parentFolderId": folder,
+        "conversationId": format!("conversation-{id}"),
+        "conversationIndex": "AQHZ",
+        "isDeliveryReceiptRequested": false,
+        "isReadReceiptRequested": false,
+        "isRead": false,
+        "isDraft": false,
+        "webLink": format!("https://outlook.office365.com/owa/?ItemID={id}"),
+        "inferenceClassification": "focused",
+        "body": {"contentType": "text", "content": ""},
+        "sender": recipient(MOCK_USER),
+        "from": recipient(MOCK_USER),
+        "toRecipients": [],
+        "ccRecipients": [],
+        "bccRecipients": [],
+        "replyTo": [],
+        "flag": {"flagStatus": "notFlagged"},
+    })
+}
+
+fn add_attachment(state: &mut State, message_id: &str, id: &str, mut attachment: Value) {
+    let size = attachment["contentBytes"].as_str().map_or(0, |c| c.len() * 3 / 4);
+    let mut full = json!({
+        "@odata.type": "#microsoft.graph.fileAttachment",
+        "@odata.mediaContentType": attachment["contentType"],
+        "contentId": null,
+        "contentLocation": null,
+        "isInline": false,
+        "lastModifiedDateTime": now(),
+        "size": size,
+    });
+    attachment["id"] = json!(id);
+    merge(&mut full, attachment);
+    state.attachments.entry(message_id.to_string()).or_default().push(full);
+    if let Some(message) = state.message(message_id) {
+        message["hasAttachments"] = json!(true);
+    }
+}
+
+/// Overwrite the fields of `target` with those of `patch`.
+fn merge(target: &mut Value, patch: Value) {
+    if let (Some(target), Value::Object(patch)) = (target.as_object_mut(), patch) {
+        target.extend(patch);
+    }
+}
+
+/// Only the fields in `$select`, plus the ones Graph always returns.
+fn select(message: &Value, query: &HashMap<String, String>) -> Value {
+    let Some(fields) = query.get("$select") else {
+        return message.clone();
+    };
+    let mut selected = Map::new();
+    for field in fields.split(',').map(str::trim).chain(["id", "@odata.etag"]) {
+        if let Some(value) = message.get(field) {
+            selected.insert(field.to_string(), value.clone());
+        }
+    }
+    Value::Object(selected)
+}
+
+fn parse_query(query: Option<&str>) -> HashMap<String, String> {
+    query
+        .unwrap_or_default()
+        .split('&')
+        .filter_map(|pair| pair.split_once('='))
+        .filter_map(|(k, v)| {
+            let k = urlencoding::decode(k).ok()?.into_owned();
+            let v = urlencoding::decode(&v.replace('+', " ")).ok()?.into_owned();
+            Some((k, v))
+        })
+        .collect()
+}
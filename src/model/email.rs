@@ -3,15 +3,18 @@ use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use std_ext::VecExt;
 
-#[derive(Serialize, Deserialize, Debug)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct Flag {
     #[serde(rename = "flagStatus")]
     pub flag_status: String,
 }
 
 /// API object for microsoft email
-#[derive(Serialize, Deserialize, Debug)]
-#[serde(rename_all = "camelCase")]
+///
+/// Graph only returns the fields named in a `$select` (plus `id` and `@odata.etag`); the others take their default
+/// values, e.g. an empty `subject` or the epoch for dates. Only rely on the fields you selected.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase", default)]
 pub struct EmailMessage {
     #[serde(rename = "@odata.etag")]
     pub etag: String,
@@ -47,17 +50,19 @@ pub struct EmailMessage {
     pub web_link: String,
 }
 
-impl Into<::email::Email> for EmailMessage {
-    fn into(self) -> ::email::Email {
+/// `from` falls back to the `sender`, then to an empty address, for messages read with a `$select` that left
+/// both out.
+impl From<EmailMessage> for ::email::Email {
+    fn from(message: EmailMessage) -> Self {
         ::email::Email {
-            from: self.from.map(|c| c.into()).unwrap(),
-            to: self.to_recipients.recollect(),
-            cc: self.cc_recipients.recollect(),
-            bcc: self.bcc_recipients.recollect(),
-            subject: self.subject,
-            body: match self.body.content_type {
-                BodyType::Text => ::email::Body::Text(self.body.content),
-                BodyType::Html => ::email::Body::Html(self.body.content),
+            from: message.from.or(message.sender).map_or_else(|| "".into(), Into::into),
+            to: message.to_recipients.recollect(),
+            cc: message.cc_recipients.recollect(),
+            bcc: message.bcc_recipients.recollect(),
+            subject: message.subject,
+            body: match message.body.content_type {
+                BodyType::Text => ::email::Body::Text(message.body.content),
+                BodyType::Html => ::email::Body::Html(message.body.content),
             },
             attachments: Vec::new(),
             reply_to_message_id: None,
@@ -65,3 +70,30 @@ impl Into<::email::Email> for EmailMessage {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_select() {
+        let s = r#"{"@odata.etag":"W/\"CQAAABYAAAB\"","id":"AAMkAGI2","subject":"Hi","from":{"emailAddress":{"name":"Alice","address":"alice@contoso.com"}}}"#;
+        let message = serde_json::from_str::<EmailMessage>(s).unwrap();
+        assert_eq!(message.subject, "Hi");
+        assert_eq!(message.from.unwrap().email_address.address, "alice@contoso.com");
+        assert!(message.to_recipients.is_empty());
+        assert_eq!(message.body.content, "");
+    }
+
+    #[test]
+    fn test_into_email_without_from() {
+        let message = serde_json::from_str::<EmailMessage>(r#"{"id":"AAMkAGI2","subject":"Hi"}"#).unwrap();
+        let email = ::email::Email::from(message);
+        assert_eq!(email.from.address, "");
+        assert_eq!(email.subject, "Hi");
+
+        let s = r#"{"id":"AAMkAGI2","sender":{"emailAddress":{"address":"bob@contoso.com"}}}"#;
+        let email = ::email::Email::from(serde_json::from_str::<EmailMessage>(s).unwrap());
+        assert_eq!(email.from.address, "bob@contoso.com");
+    }
+}
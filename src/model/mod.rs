@@ -4,10 +4,16 @@ mod recipient;
 mod page;
 mod body;
 mod attachment;
+mod subscription;
+mod notification;
+mod projection;
 
 pub use me::*;
 pub use page::*;
 pub use email::*;
 pub use recipient::*;
 pub use body::*;
-pub use attachment::*;
\ No newline at end of file
+pub use attachment::*;
+pub use subscription::*;
+pub use notification::*;
+pub use projection::*;
\ No newline at end of file
@@ -0,0 +1,183 @@
+use crate::model::ChangeType;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// The body Graph POSTs to a subscription's `notificationUrl`. Notifications are batched.
+/// see https://learn.microsoft.com/en-us/graph/change-notifications-delivery-webhooks
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct NotificationCollection {
+    pub value: Vec<Notification>,
+    /// Present on rich notifications. JWTs proving the notifications were sent by Graph.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub validation_tokens: Vec<String>,
+}
+
+impl NotificationCollection {
+    pub fn from_slice(body: &[u8]) -> serde_json::Result<Self> {
+        serde_json::from_slice(body)
+    }
+
+    /// Whether every notification in the batch carries the `clientState` we registered the subscription with.
+    /// Notifications that fail this check didn't come from Graph and must be dropped.
+    pub fn verify_client_state(&self, expected: &str) -> bool {
+        self.value.iter().all(|n| n.client_state.as_deref() == Some(expected))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Notification {
+    #[serde(default)]
+    pub id: Option<String>,
+    pub subscription_id: String,
+    pub subscription_expiration_date_time: DateTime<Utc>,
+    #[serde(default)]
+    pub client_state: Option<String>,
+    pub change_type: ChangeType,
+    pub resource: String,
+    #[serde(default)]
+    pub tenant_id: Option<String>,
+    #[serde(default)]
+    pub resource_data: Option<ResourceData>,
+    /// Present when the subscription was created with `includeResourceData`.
+    /// Decrypt it with [`crate::encryption::NotificationCertificate`].
+    #[serde(default)]
+    pub encrypted_content: Option<EncryptedContent>,
+}
+
+impl Notification {
+    /// The id of the message that changed, if Graph included it.
+    pub fn message_id(&self) -> Option<&str> {
+        self.resource_data.as_ref().and_then(|r| r.id.as_deref())
+    }
+}
+
+/// Sent to a subscription's `lifecycleNotificationUrl` when something happens to the subscription itself.
+/// see https://learn.microsoft.com/en-us/graph/change-notifications-lifecycle-events
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LifecycleNotificationCollection {
+    pub value: Vec<LifecycleNotification>,
+}
+
+impl LifecycleNotificationCollection {
+    pub fn from_slice(body: &[u8]) -> serde_json::Result<Self> {
+        serde_json::from_slice(body)
+    }
+
+    /// See [`NotificationCollection::verify_client_state`].
+    pub fn verify_client_state(&self, expected: &str) -> bool {
+        self.value.iter().all(|n| n.client_state.as_deref() == Some(expected))
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub enum LifecycleEvent {
+    /// The access token behind the subscription is about to expire, or the app's access was revoked.
+    /// Recover with [`crate::MicrosoftClient::reauthorize_subscription`] (or a renewal) using a fresh token.
+    /// If nothing is done, Graph removes the subscription.
+    ReauthorizationRequired,
+    /// Graph removed the subscription, e.g. because reauthorization didn't happen in time or a password changed.
+    /// Recover by creating a new subscription, then catching up as for `Missed`.
+    SubscriptionRemoved,
+    /// Some change notifications could not be delivered.
+    /// Recover by listing what changed since the last processed notification, see [`crate::MicrosoftClient::catch_up`].
+    Missed,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct LifecycleNotification {
+    pub lifecycle_event: LifecycleEvent,
+    pub subscription_id: String,
+    #[serde(default)]
+    pub subscription_expiration_date_time: Option<DateTime<Utc>>,
+    #[serde(default)]
+    pub client_state: Option<String>,
+    #[serde(default)]
+    pub tenant_id: Option<String>,
+    #[serde(default)]
+    pub resource: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct ResourceData {
+    #[serde(default, rename = "@odata.type")]
+    pub odata_type: Option<String>,
+    #[serde(default, rename = "@odata.id")]
+    pub odata_id: Option<String>,
+    #[serde(default, rename = "@odata.etag")]
+    pub etag: Option<String>,
+    #[serde(default)]
+    pub id: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct EncryptedContent {
+    /// base64 encoded, AES-CBC encrypted resource
+    pub data: String,
+    /// base64 encoded HMAC-SHA256 of `data`
+    pub data_signature: String,
+    /// base64 encoded, RSA-OAEP encrypted symmetric key
+    pub data_key: String,
+    pub encryption_certificate_id: String,
+    #[serde(default)]
+    pub encryption_certificate_thumbprint: Option<String>,
+}
+
+/// When a subscription is created, Graph POSTs to the `notificationUrl` with a `validationToken` query parameter.
+/// The endpoint must answer within 10 seconds with `200 OK`, `Content-Type: text/plain`, and the decoded token as the body.
+///
+/// Pass either the full request url or just its query string. Returns the decoded token if this is a validation request.
+/// see https://learn.microsoft.com/en-us/graph/change-notifications-delivery-webhooks#notificationurl-validation
+pub fn validation_token(url_or_query: &str) -> Option<String> {
+    let query = match url_or_query.split_once('?') {
+        Some((_, q)) => q,
+        None => url_or_query,
+    };
+    query.split('&').find_map(|pair| {
+        let (k, v) = pair.split_once('=')?;
+        if k != "validationToken" {
+            return None;
+        }
+        // form encoding uses + for spaces, which percent-decoding alone doesn't handle
+        let v = v.replace('+', " ");
+        urlencoding::decode(&v).ok().map(|v| v.into_owned())
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_notification_deserialization() {
+        let s = r##"{"value":[{"id":"lsgTZMr9KwAAA","subscriptionId":"{subscription_guid}","subscriptionExpirationDateTime":"2016-03-19T22:11:09.952Z","clientState":"secretClientValue","changeType":"created","resource":"users/{user_guid}@{tenant_guid}/messages/{long_id_string}","tenantId":"84bd8158-6d4d-4958-8b9f-9d6445542f95","resourceData":{"@odata.type":"#Microsoft.Graph.Message","@odata.id":"Users/{user_guid}@{tenant_guid}/Messages/{long_id_string}","@odata.etag":"W/\"CQAAABYAAADkrWGo7bouTKlsgTZMr9KwAAAUWRHf\"","id":"{long_id_string}"}}]}"##;
+        let n = NotificationCollection::from_slice(s.as_bytes()).unwrap();
+        assert!(n.verify_client_state("secretClientValue"));
+        assert!(!n.verify_client_state("other"));
+        assert_eq!(n.value[0].change_type, ChangeType::Created);
+        assert_eq!(n.value[0].message_id(), Some("{long_id_string}"));
+    }
+
+    #[test]
+    fn test_lifecycle_notification_deserialization() {
+        let s = r#"{"value":[{"lifecycleEvent":"reauthorizationRequired","subscriptionId":"e1d0b4e4-0bd9-4b28-ae88-b2c0f8e7c3a8","subscriptionExpirationDateTime":"2019-09-18T00:52:05.5391734+00:00","clientState":"secretClientValue","tenantId":"84bd8158-6d4d-4958-8b9f-9d6445542f95"}]}"#;
+        let n = LifecycleNotificationCollection::from_slice(s.as_bytes()).unwrap();
+        assert!(n.verify_client_state("secretClientValue"));
+        assert_eq!(n.value[0].lifecycle_event, LifecycleEvent::ReauthorizationRequired);
+    }
+
+    #[test]
+    fn test_validation_token() {
+        let url = "https://example.com/notify?validationToken=Validation%3a+Testing+client+application+reachability";
+        assert_eq!(
+            validation_token(url).as_deref(),
+            Some("Validation: Testing client application reachability")
+        );
+        assert_eq!(validation_token("/notify"), None);
+    }
+}
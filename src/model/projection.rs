@@ -0,0 +1,148 @@
+use serde::de::DeserializeOwned;
+
+/// A type to read messages as, with only the fields it needs. See [`crate::projection!`] to declare one, and
+/// `select_as` on [`crate::MicrosoftClient::list_messages`] and [`crate::MicrosoftClient::get_message`].
+///
+/// ```
+/// use microsoft_mail::model::{Projection, Recipient};
+/// use serde::Deserialize;
+///
+/// #[derive(Deserialize)]
+/// #[serde(rename_all = "camelCase")]
+/// struct Overview {
+///     id: String,
+///     subject: String,
+///     from: Option<Recipient>,
+/// }
+///
+/// impl Projection for Overview {
+///     const FIELDS: &'static [&'static str] = &["id", "subject", "from"];
+/// }
+/// ```
+pub trait Projection: DeserializeOwned {
+    /// The Graph properties to `$select`, e.g. `receivedDateTime`.
+    const FIELDS: &'static [&'static str];
+}
+
+/// Declares a struct that deserializes from Graph's camelCase properties, and implements [`Projection`] with its
+/// field names. The struct derives `serde::Deserialize`, so the caller needs `serde` as a dependency.
+///
+/// ```
+/// use chrono::{DateTime, Utc};
+/// use microsoft_mail::model::{Projection, Recipient};
+///
+/// microsoft_mail::projection! {
+///     #[derive(Debug, Clone)]
+///     pub struct Received {
+///         pub id: String,
+///         pub received_date_time: DateTime<Utc>,
+///         pub from: Option<Recipient>,
+///     }
+/// }
+///
+/// assert_eq!(Received::FIELDS, ["id", "receivedDateTime", "from"]);
+/// ```
+///
+/// Fields renamed with `#[serde(rename)]` would be selected by their Rust name; implement [`Projection`] by hand
+/// for those.
+#[macro_export]
+macro_rules! projection {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $($(#[$field_meta:meta])* $field_vis:vis $field:ident : $ty:ty),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(::serde::Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        $vis struct $name {
+            $($(#[$field_meta])* $field_vis $field: $ty),*
+        }
+
+        impl $crate::model::Projection for $name {
+            const FIELDS: &'static [&'static str] = &[$({
+                const NAME: &str = stringify!($field);
+                const BYTES: [u8; $crate::model::__camel_case_len(NAME)] = $crate::model::__camel_case(NAME);
+                const CAMEL: &[u8] = &BYTES;
+                match ::core::str::from_utf8(CAMEL) {
+                    Ok(name) => name,
+                    Err(_) => panic!("field names are ascii"),
+                }
+            }),*];
+        }
+    };
+}
+
+/// The length of `snake_case` in camelCase, the way serde's `rename_all = "camelCase"` spells it.
+#[doc(hidden)]
+pub const fn __camel_case_len(snake_case: &str) -> usize {
+    let bytes = snake_case.as_bytes();
+    let mut len = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'_' {
+            len += 1;
+        }
+        i += 1;
+    }
+    len
+}
+
+#[doc(hidden)]
+pub const fn __camel_case<const N: usize>(snake_case: &str) -> [u8; N] {
+    let bytes = snake_case.as_bytes();
+    let mut camel = [0u8; N];
+    let mut upper = false;
+    let (mut i, mut j) = (0, 0);
+    while i < bytes.len() {
+        if bytes[i] == b'_' {
+            upper = true;
+        } else {
+            camel[j] = if upper { bytes[i].to_ascii_uppercase() } else { bytes[i] };
+            upper = false;
+            j += 1;
+        }
+        i += 1;
+    }
+    camel
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockGraph;
+    use crate::model::Recipient;
+    use chrono::{DateTime, Utc};
+    use serde_json::json;
+
+    crate::projection! {
+        struct Received {
+            id: String,
+            received_date_time: DateTime<Utc>,
+            from: Option<Recipient>,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_select_as() {
+        assert_eq!(Received::FIELDS, ["id", "receivedDateTime", "from"]);
+        let graph = MockGraph::start().await;
+        let id = graph.insert_message(json!({"from": {"emailAddress": {"address": "alice@contoso.com"}}}));
+        graph.insert_message(json!({}));
+        let client = graph.client();
+
+        let page = client.list_messages().top(1).select_as::<Received>().await.unwrap();
+        assert_eq!(page[0].id, id);
+        assert_eq!(page[0].from.as_ref().unwrap().email_address.address, "alice@contoso.com");
+        let next = page.next_link.clone().unwrap();
+        let page = client.list_messages().next(next).select_as::<Received>().await.unwrap();
+        assert_eq!(page.len(), 1);
+
+        let message = client.get_message(&id).select_as::<Received>().await.unwrap();
+        assert_eq!(message.id, id);
+        assert!(message.received_date_time <= Utc::now());
+        let query = graph.requests().last().unwrap().query.clone().unwrap();
+        assert_eq!(query, "$select=id%2CreceivedDateTime%2Cfrom");
+    }
+}
@@ -0,0 +1,176 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+
+/// Outlook message subscriptions can live for at most 10,070 minutes (just under 7 days).
+/// see https://learn.microsoft.com/en-us/graph/api/resources/subscription#subscription-lifetime
+pub const MAX_MESSAGE_SUBSCRIPTION_MINUTES: i64 = 10_070;
+
+/// Subscriptions that include resource data can live for at most one day.
+pub const MAX_RICH_MESSAGE_SUBSCRIPTION_MINUTES: i64 = 1_440;
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeType {
+    Created,
+    Updated,
+    Deleted,
+}
+
+impl fmt::Display for ChangeType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChangeType::Created => write!(f, "created"),
+            ChangeType::Updated => write!(f, "updated"),
+            ChangeType::Deleted => write!(f, "deleted"),
+        }
+    }
+}
+
+/// Graph sends the change types of a subscription as a single comma separated string, e.g. "created,updated"
+pub(crate) mod change_types {
+    use super::ChangeType;
+    use serde::de::{Error, IntoDeserializer};
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S: Serializer>(value: &[ChangeType], serializer: S) -> Result<S::Ok, S::Error> {
+        let s = value.iter().map(|c| c.to_string()).collect::<Vec<_>>().join(",");
+        serializer.serialize_str(&s)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<ChangeType>, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.split(',')
+            .map(str::trim)
+            .filter(|s| !s.is_empty())
+            .map(|s| ChangeType::deserialize(s.into_deserializer()).map_err(|e: serde::de::value::Error| D::Error::custom(e)))
+            .collect()
+    }
+}
+
+/// The mail resource a subscription watches.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum SubscriptionResource {
+    /// All messages in a mailbox. `None` means the signed in user (`/me`).
+    Messages { mailbox: Option<String> },
+    /// Messages in a single folder, e.g. `inbox`. Well-known folder names and folder ids both work.
+    Folder { mailbox: Option<String>, folder: String },
+    /// Any other resource path, passed through verbatim.
+    Other(String),
+}
+
+impl SubscriptionResource {
+    pub fn messages(mailbox: Option<String>) -> Self {
+        Self::Messages { mailbox }
+    }
+
+    pub fn inbox(mailbox: Option<String>) -> Self {
+        Self::Folder {
+            mailbox,
+            folder: "inbox".to_string(),
+        }
+    }
+}
+
+impl fmt::Display for SubscriptionResource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fn owner(mailbox: &Option<String>) -> String {
+            match mailbox {
+                Some(m) => format!("/users/{m}"),
+                None => "/me".to_string(),
+            }
+        }
+        match self {
+            SubscriptionResource::Messages { mailbox } => write!(f, "{}/messages", owner(mailbox)),
+            SubscriptionResource::Folder { mailbox, folder } => {
+                write!(f, "{}/mailFolders('{folder}')/messages", owner(mailbox))
+            }
+            SubscriptionResource::Other(s) => write!(f, "{s}"),
+        }
+    }
+}
+
+impl Serialize for SubscriptionResource {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for SubscriptionResource {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        // Graph echoes the resource back in whatever form it was given, so we don't try to parse it.
+        String::deserialize(deserializer).map(SubscriptionResource::Other)
+    }
+}
+
+/// API object for a Graph change notification subscription
+/// see https://learn.microsoft.com/en-us/graph/api/resources/subscription
+#[derive(Debug, Serialize, Deserialize, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct Subscription {
+    pub id: String,
+    pub resource: String,
+    #[serde(with = "change_types")]
+    pub change_type: Vec<ChangeType>,
+    pub notification_url: String,
+    #[serde(default)]
+    pub lifecycle_notification_url: Option<String>,
+    #[serde(default)]
+    pub client_state: Option<String>,
+    pub expiration_date_time: DateTime<Utc>,
+    #[serde(default)]
+    pub application_id: Option<String>,
+    #[serde(default)]
+    pub creator_id: Option<String>,
+    #[serde(default)]
+    pub latest_supported_tls_version: Option<String>,
+    #[serde(default)]
+    pub include_resource_data: Option<bool>,
+    #[serde(default)]
+    pub encryption_certificate_id: Option<String>,
+}
+
+impl Subscription {
+    /// Time left until Graph deletes the subscription. Negative if it already expired.
+    pub fn expires_in(&self) -> Duration {
+        self.expiration_date_time - Utc::now()
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expiration_date_time <= Utc::now()
+    }
+
+    /// Whether the subscription expires within `margin` and should be renewed now.
+    pub fn needs_renewal(&self, margin: Duration) -> bool {
+        self.expires_in() <= margin
+    }
+}
+
+/// The latest expiration Graph will accept for a message subscription created or renewed now.
+pub fn max_message_subscription_expiration() -> DateTime<Utc> {
+    Utc::now() + Duration::minutes(MAX_MESSAGE_SUBSCRIPTION_MINUTES)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_subscription_deserialization() {
+        let s = r#"{"@odata.context":"https://graph.microsoft.com/v1.0/$metadata#subscriptions/$entity","id":"7f105c7d-2dc5-4530-97cd-4e7ae6534c07","resource":"me/mailFolders('inbox')/messages","applicationId":"24d3b144-21ae-4080-943f-7067b395b913","changeType":"created,updated","clientState":"secretClientValue","notificationUrl":"https://webhook.azurewebsites.net/api/send/myNotifyClient","expirationDateTime":"2016-11-20T18:23:45.9356913Z","creatorId":"8ee44408-0679-472c-bc2a-692812af3437","latestSupportedTlsVersion":"v1_2","includeResourceData":false}"#;
+        let sub = serde_json::from_str::<Subscription>(s).unwrap();
+        assert_eq!(sub.change_type, vec![ChangeType::Created, ChangeType::Updated]);
+        assert!(sub.is_expired());
+        let v = serde_json::to_value(&sub).unwrap();
+        assert_eq!(v["changeType"], "created,updated");
+    }
+
+    #[test]
+    fn test_subscription_resource() {
+        assert_eq!(SubscriptionResource::messages(None).to_string(), "/me/messages");
+        assert_eq!(
+            SubscriptionResource::inbox(Some("a@b.com".to_string())).to_string(),
+            "/users/a@b.com/mailFolders('inbox')/messages"
+        );
+    }
+}
@@ -0,0 +1,403 @@
+use crate::auth::Scope;
+use crate::request::RequiredPermissions;
+use crate::retry::parse_retry_after;
+use crate::{FluentRequest, GraphError, MicrosoftClient, MicrosoftError, MicrosoftResult};
+use futures::future::BoxFuture;
+use httpclient::{InMemoryResponseExt, Method, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::{BTreeMap, HashMap};
+use std::future::IntoFuture;
+use std::marker::PhantomData;
+
+/// Graph rejects batches with more than 20 requests.
+pub const MAX_BATCH_SIZE: usize = 20;
+
+/// A request that can be sent as part of a [`BatchRequest`]. Its permissions are checked by the batch's preflight.
+pub trait Batchable: RequiredPermissions {
+    type Output: DeserializeOwned;
+
+    fn to_batch_item(&self) -> BatchItem;
+}
+
+/// One request inside a `$batch`. `url` is relative to the api version, e.g. `/me/messages?$top=5`.
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchItem {
+    #[serde(serialize_with = "serialize_method")]
+    pub method: Method,
+    pub url: String,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub headers: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub body: Option<Value>,
+    /// Why the body passed to [`BatchItem::json`] couldn't be serialized. Sending the batch fails with it.
+    #[serde(skip)]
+    body_error: Option<String>,
+}
+
+fn serialize_method<S: serde::Serializer>(method: &Method, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(method.as_str())
+}
+
+impl BatchItem {
+    pub fn new(method: Method, url: impl Into<String>) -> Self {
+        let mut headers = BTreeMap::new();
+        // requests inside a batch don't inherit the headers of the batch itself. see [`MicrosoftClient::authorize`]
+        headers.insert("Prefer".to_string(), r#"IdType="ImmutableId""#.to_string());
+        Self {
+            method,
+            url: relative_url(&url.into()),
+            headers,
+            body: None,
+            body_error: None,
+        }
+    }
+
+    pub fn json(mut self, body: impl Serialize) -> Self {
+        match serde_json::to_value(body) {
+            Ok(body) => self.body = Some(body),
+            Err(e) => self.body_error = Some(e.to_string()),
+        }
+        self.headers.insert("Content-Type".to_string(), "application/json".to_string());
+        self
+    }
+}
+
+/// `@odata.nextLink` urls are absolute, but batch urls must be relative to the api version.
+fn relative_url(url: &str) -> String {
+    if !url.starts_with("http") {
+        return url.to_string();
+    }
+    let path = url.splitn(4, '/').nth(3).unwrap_or_default();
+    let path = path.split_once('/').map_or("", |(_version, rest)| rest);
+    format!("/{path}")
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct BatchEntry {
+    id: String,
+    #[serde(flatten)]
+    item: BatchItem,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    depends_on: Vec<String>,
+    #[serde(skip)]
+    permissions: &'static [&'static [Scope]],
+}
+
+/// Identifies a request in a [`BatchRequest`], and the type of its response.
+#[derive(Debug)]
+pub struct BatchHandle<T> {
+    id: String,
+    _output: PhantomData<fn() -> T>,
+}
+
+impl<T> BatchHandle<T> {
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+}
+
+impl<T> Clone for BatchHandle<T> {
+    fn clone(&self) -> Self {
+        Self {
+            id: self.id.clone(),
+            _output: PhantomData,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct BatchRequest {
+    entries: Vec<BatchEntry>,
+}
+
+impl MicrosoftClient {
+    /// Combine many requests into `POST /$batch` calls. Batches larger than [`MAX_BATCH_SIZE`] are split
+    /// into several calls, keeping requests that depend on each other together. Sending fails with
+    /// [`MicrosoftError::Config`] if more than [`MAX_BATCH_SIZE`] requests are chained together.
+    /// see https://learn.microsoft.com/en-us/graph/json-batching
+    pub fn batch(&self) -> FluentRequest<'_, BatchRequest> {
+        FluentRequest {
+            client: self,
+            options: Default::default(),
+            params: BatchRequest::default(),
+        }
+    }
+}
+
+impl<'a> FluentRequest<'a, BatchRequest> {
+    pub fn add<R: Batchable>(&mut self, request: R) -> BatchHandle<R::Output> {
+        self.push(request.to_batch_item(), Vec::new(), request.required_permissions())
+    }
+
+    /// Add a request that only runs once `after` succeeded. Graph skips it with a 424 otherwise.
+    pub fn add_after<R: Batchable, T>(&mut self, request: R, after: &BatchHandle<T>) -> BatchHandle<R::Output> {
+        self.push(request.to_batch_item(), vec![after.id.clone()], request.required_permissions())
+    }
+
+    /// The permissions of every request added so far. See [`RequiredPermissions`].
+    pub fn required_permissions(&self) -> Vec<&'static [Scope]> {
+        let mut required: Vec<&'static [Scope]> = Vec::new();
+        for permission in self.params.entries.iter().flat_map(|e| e.permissions.iter().copied()) {
+            if !required.contains(&permission) {
+                required.push(permission);
+            }
+        }
+        required
+    }
+
+    pub fn len(&self) -> usize {
+        self.params.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.params.entries.is_empty()
+    }
+
+    fn push<T>(
+        &mut self, item: BatchItem, depends_on: Vec<String>, permissions: &'static [&'static [Scope]],
+    ) -> BatchHandle<T> {
+        let id = (self.params.entries.len() + 1).to_string();
+        self.params.entries.push(BatchEntry {
+            id: id.clone(),
+            item,
+            depends_on,
+            permissions,
+        });
+        BatchHandle {
+            id,
+            _output: PhantomData,
+        }
+    }
+}
+
+impl BatchRequest {
+    /// Split the entries into batches of at most [`MAX_BATCH_SIZE`], keeping entries connected by dependencies in the
+    /// same batch. Fails with [`MicrosoftError::Config`] if more than [`MAX_BATCH_SIZE`] entries are connected.
+    fn chunks(self) -> MicrosoftResult<Vec<Vec<BatchEntry>>> {
+        // union-find over entry indices, joining each entry with every entry it depends on
+        fn root(parent: &mut [usize], mut i: usize) -> usize {
+            while parent[i] != i {
+                parent[i] = parent[parent[i]];
+                i = parent[i];
+            }
+            i
+        }
+        let index: HashMap<&str, usize> = self.entries.iter().enumerate().map(|(i, e)| (e.id.as_str(), i)).collect();
+        let mut parent: Vec<usize> = (0..self.entries.len()).collect();
+        for (i, entry) in self.entries.iter().enumerate() {
+            for dependency in &entry.depends_on {
+                let Some(&j) = index.get(dependency.as_str()) else { continue };
+                let (a, b) = (root(&mut parent, i), root(&mut parent, j));
+                // the earlier entry stays the root, so groups keep the order of their first entry
+                parent[a.max(b)] = a.min(b);
+            }
+        }
+        let mut group_of: HashMap<usize, usize> = HashMap::new();
+        let mut groups: Vec<Vec<BatchEntry>> = Vec::new();
+        for (i, entry) in self.entries.into_iter().enumerate() {
+            let group = *group_of.entry(root(&mut parent, i)).or_insert_with(|| {
+                groups.push(Vec::new());
+                groups.len() - 1
+            });
+            groups[group].push(entry);
+        }
+        let mut chunks: Vec<Vec<BatchEntry>> = Vec::new();
+        for group in groups {
+            if group.len() > MAX_BATCH_SIZE {
+                return Err(MicrosoftError::Config(format!(
+                    "{} batch requests depend on each other, but a batch holds at most {MAX_BATCH_SIZE}",
+                    group.len()
+                )));
+            }
+            match chunks.last_mut() {
+                Some(chunk) if chunk.len() + group.len() <= MAX_BATCH_SIZE => chunk.extend(group),
+                _ => chunks.push(group),
+            }
+        }
+        Ok(chunks)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchResponseBody {
+    responses: Vec<BatchResponseItem>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BatchResponseItem {
+    id: String,
+    status: u16,
+    #[serde(default)]
+    headers: BTreeMap<String, String>,
+    #[serde(default)]
+    body: Value,
+}
+
+/// The responses of a [`BatchRequest`], looked up with the handles returned when adding requests.
+#[derive(Debug, Default)]
+pub struct BatchResponse {
+    responses: HashMap<String, BatchResponseItem>,
+}
+
+impl BatchResponse {
+    /// The response for one request. Failed requests (including ones skipped because a dependency failed)
+    /// come back as the same [`MicrosoftError`] a standalone request would.
+    pub fn get<T: DeserializeOwned>(&self, handle: &BatchHandle<T>) -> MicrosoftResult<T> {
+        let Some(item) = self.responses.get(&handle.id) else {
+            return Err(MicrosoftError::UnexpectedResponse(format!("no response for batch request {}", handle.id)));
+        };
+        let status = StatusCode::from_u16(item.status)
+            .map_err(|_| MicrosoftError::UnexpectedResponse(format!("invalid status {}", item.status)))?;
+        if status.is_client_error() || status.is_server_error() {
+            let mut error = GraphError::from_body(status, &item.body);
+            let header = |name: &str| {
+                let found = item.headers.iter().find(|(k, _)| k.eq_ignore_ascii_case(name));
+                found.map(|(_, v)| v.clone())
+            };
+            error.request_id = error.request_id.or_else(|| header("request-id"));
+            let retry_after = header("retry-after").and_then(|v| parse_retry_after(&v));
+            return Err(MicrosoftError::from_graph_error(error, retry_after));
+        }
+        Ok(serde_json::from_value(item.body.clone())?)
+    }
+
+    pub fn status<T>(&self, handle: &BatchHandle<T>) -> Option<StatusCode> {
+        self.responses.get(&handle.id).and_then(|r| StatusCode::from_u16(r.status).ok())
+    }
+}
+
+impl<'a> IntoFuture for FluentRequest<'a, BatchRequest> {
+    type Output = MicrosoftResult<BatchResponse>;
+    type IntoFuture = BoxFuture<'a, Self::Output>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(async move {
+            self.client.preflight(&self.required_permissions()).await?;
+            if let Some(error) = self.params.entries.iter().find_map(|e| e.item.body_error.as_ref()) {
+                return Err(<serde_json::Error as serde::ser::Error>::custom(error).into());
+            }
+            let mut response = BatchResponse::default();
+            for chunk in self.params.chunks()? {
+                let mut r = self.client.client.post("/$batch");
+                r = r.json(serde_json::json!({ "requests": chunk }));
+                r = self.client.authorize(r, &self.options);
+                let res = r.await?;
+                let body: BatchResponseBody = res.json()?;
+                response.responses.extend(body.responses.into_iter().map(|r| (r.id.clone(), r)));
+            }
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::StaticToken;
+    use crate::request::{READ_MAIL, WRITE_MAIL};
+    use crate::MicrosoftAuth;
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+
+    #[tokio::test]
+    async fn test_preflight() {
+        let claims = URL_SAFE_NO_PAD.encode(r#"{"tid":"t","scp":"Mail.Read"}"#);
+        // nothing is listening on this port, so the test fails if a request is sent
+        let client = MicrosoftClient::builder()
+            .http_client(httpclient::Client::new())
+            .base_url("http://127.0.0.1:9")
+            .auth(MicrosoftAuth::provider(StaticToken::new(format!("e30.{claims}.sig"))))
+            .preflight(true)
+            .build()
+            .unwrap();
+        let mut batch = client.batch();
+        let read = batch.add(client.get_message("1"));
+        batch.add_after(client.update_message("1").is_read(true), &read);
+        batch.add(client.get_message("2"));
+        assert_eq!(batch.required_permissions(), [READ_MAIL, WRITE_MAIL]);
+        let Err(err) = batch.await else {
+            panic!("expected the preflight to fail")
+        };
+        assert!(matches!(err, MicrosoftError::MissingPermissions(missing) if missing == [Scope::MailReadWrite]));
+    }
+
+    #[tokio::test]
+    async fn test_invalid_body() {
+        let client = MicrosoftClient::builder()
+            .http_client(httpclient::Client::new())
+            .base_url("http://127.0.0.1:9")
+            .auth(MicrosoftAuth::provider(StaticToken::new("token")))
+            .build()
+            .unwrap();
+        let mut batch = client.batch();
+        let body = HashMap::from([((1, 2), "non-string keys can't be JSON")]);
+        batch.push::<Value>(BatchItem::new(Method::POST, "/me/messages").json(body), Vec::new(), &[]);
+        let Err(err) = batch.await else {
+            panic!("expected the body to fail to serialize")
+        };
+        assert!(matches!(err, MicrosoftError::Protocol(httpclient::ProtocolError::JsonError(_))), "{err}");
+    }
+
+    #[test]
+    fn test_relative_url() {
+        assert_eq!(relative_url("/me/messages"), "/me/messages");
+        assert_eq!(
+            relative_url("https://graph.microsoft.com/v1.0/me/messages?$skip=10"),
+            "/me/messages?$skip=10"
+        );
+    }
+
+    #[test]
+    fn test_chunks() {
+        let mut batch = BatchRequest::default();
+        let entry = |id: usize, depends_on: Option<usize>| BatchEntry {
+            id: id.to_string(),
+            item: BatchItem::new(Method::GET, "/me"),
+            depends_on: depends_on.map(|d| d.to_string()).into_iter().collect(),
+            permissions: &[],
+        };
+        for i in 1..=19 {
+            batch.entries.push(entry(i, None));
+        }
+        // 20 and 21 depend on each other, so they can't be split across batches
+        batch.entries.push(entry(20, None));
+        batch.entries.push(entry(21, Some(20)));
+        batch.entries.push(entry(22, None));
+        let chunks = batch.chunks().unwrap();
+        assert_eq!(chunks.iter().map(Vec::len).collect::<Vec<_>>(), vec![19, 3]);
+    }
+
+    #[test]
+    fn test_chunks_multiple_dependencies() {
+        let entry = |id: usize, depends_on: &[usize]| BatchEntry {
+            id: id.to_string(),
+            item: BatchItem::new(Method::GET, "/me"),
+            depends_on: depends_on.iter().map(|d| d.to_string()).collect(),
+            permissions: &[],
+        };
+        let mut batch = BatchRequest::default();
+        for i in 1..=18 {
+            batch.entries.push(entry(i, &[]));
+        }
+        // 19 joins the groups of 1 and 18, so 1, 18, 19 and 20 must share a batch
+        batch.entries.push(entry(19, &[1, 18]));
+        batch.entries.push(entry(20, &[19]));
+        batch.entries.push(entry(21, &[]));
+        let chunks = batch.chunks().unwrap();
+        let ids = |chunk: &Vec<BatchEntry>| chunk.iter().map(|e| e.id.parse::<usize>().unwrap()).collect::<Vec<_>>();
+        let together = chunks.iter().find(|c| ids(c).contains(&1)).unwrap();
+        assert!([18, 19, 20].iter().all(|id| ids(together).contains(id)));
+        assert!(chunks.iter().all(|c| c.len() <= MAX_BATCH_SIZE));
+        assert_eq!(chunks.iter().map(Vec::len).sum::<usize>(), 21);
+
+        let mut batch = BatchRequest::default();
+        batch.entries.push(entry(1, &[]));
+        for i in 2..=21 {
+            batch.entries.push(entry(i, &[i - 1]));
+        }
+        assert!(matches!(batch.chunks(), Err(MicrosoftError::Config(_))));
+    }
+}
@@ -0,0 +1,177 @@
+use crate::auth::Scope;
+use crate::encryption::NotificationCertificate;
+use crate::model::{
+    change_types, max_message_subscription_expiration, ChangeType, Subscription, SubscriptionResource,
+    MAX_RICH_MESSAGE_SUBSCRIPTION_MINUTES,
+};
+use crate::request::{RequiredPermissions, READ_MAIL};
+use crate::{FluentRequest, MicrosoftClient, MicrosoftError, MicrosoftResult};
+use chrono::{DateTime, Duration, Utc};
+use futures::future::BoxFuture;
+use httpclient::InMemoryResponseExt;
+use serde::Serialize;
+use std::future::IntoFuture;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CreateSubscriptionRequest {
+    #[serde(with = "change_types")]
+    change_type: Vec<ChangeType>,
+    notification_url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lifecycle_notification_url: Option<String>,
+    resource: String,
+    expiration_date_time: DateTime<Utc>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_state: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    include_resource_data: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encryption_certificate: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    encryption_certificate_id: Option<String>,
+}
+
+impl MicrosoftClient {
+    /// Subscribe to change notifications for a mail resource. Graph will immediately POST a validation request
+    /// to `notification_url` (see [`crate::model::validation_token`]), and the subscription is only created if that succeeds.
+    ///
+    /// Defaults to `created` notifications, expiring as late as Graph allows for messages.
+    pub fn create_subscription(
+        &self, resource: SubscriptionResource, notification_url: impl Into<String>,
+    ) -> FluentRequest<'_, CreateSubscriptionRequest> {
+        FluentRequest {
+            client: self,
+            options: Default::default(),
+            params: CreateSubscriptionRequest {
+                change_type: vec![ChangeType::Created],
+                notification_url: notification_url.into(),
+                lifecycle_notification_url: None,
+                resource: resource.to_string(),
+                expiration_date_time: max_message_subscription_expiration(),
+                client_state: None,
+                include_resource_data: None,
+                encryption_certificate: None,
+                encryption_certificate_id: None,
+            },
+        }
+    }
+}
+
+impl<'a> FluentRequest<'a, CreateSubscriptionRequest> {
+    pub fn change_types(mut self, change_types: impl Into<Vec<ChangeType>>) -> Self {
+        self.params.change_type = change_types.into();
+        self
+    }
+
+    /// A secret (max 128 chars) Graph echoes back in every notification, so the receiver can verify the sender.
+    pub fn client_state(mut self, client_state: impl Into<String>) -> Self {
+        self.params.client_state = Some(client_state.into());
+        self
+    }
+
+    pub fn lifecycle_notification_url(mut self, url: impl Into<String>) -> Self {
+        self.params.lifecycle_notification_url = Some(url.into());
+        self
+    }
+
+    pub fn expiration(mut self, expiration: DateTime<Utc>) -> Self {
+        self.params.expiration_date_time = expiration;
+        self
+    }
+
+    /// Limit the properties included in rich notifications.
+    pub fn select(mut self, select: &[&str]) -> Self {
+        self.params.resource = format!("{}?$select={}", self.params.resource, select.join(","));
+        self
+    }
+
+    /// Ask Graph to include the changed message in each notification, encrypted with `certificate`.
+    /// Decrypt it with [`crate::model::Notification::decrypt_message`].
+    ///
+    /// Rich subscriptions can live for at most a day, so the expiration is capped accordingly when the request is sent.
+    /// Graph also requires a `lifecycle_notification_url` if the expiration is more than an hour out; sending fails
+    /// with [`crate::MicrosoftError::Config`] without one.
+    pub fn include_resource_data(mut self, certificate: &NotificationCertificate) -> Self {
+        self.params.include_resource_data = Some(true);
+        self.params.encryption_certificate = Some(certificate.encryption_certificate());
+        self.params.encryption_certificate_id = Some(certificate.id().to_string());
+        self
+    }
+}
+
+impl CreateSubscriptionRequest {
+    /// Apply Graph's limits for rich subscriptions, whatever order the builder methods were called in.
+    fn validate(&mut self) -> MicrosoftResult<()> {
+        if self.include_resource_data != Some(true) {
+            return Ok(());
+        }
+        let now = Utc::now();
+        let max = now + Duration::minutes(MAX_RICH_MESSAGE_SUBSCRIPTION_MINUTES);
+        self.expiration_date_time = self.expiration_date_time.min(max);
+        if self.expiration_date_time > now + Duration::hours(1) && self.lifecycle_notification_url.is_none() {
+            return Err(MicrosoftError::Config(
+                "subscriptions with resource data that expire more than an hour out need a lifecycle_notification_url".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl RequiredPermissions for FluentRequest<'_, CreateSubscriptionRequest> {
+    fn required_permissions(&self) -> &'static [&'static [Scope]] {
+        &[READ_MAIL]
+    }
+}
+
+impl<'a> IntoFuture for FluentRequest<'a, CreateSubscriptionRequest> {
+    type Output = MicrosoftResult<Subscription>;
+    type IntoFuture = BoxFuture<'a, Self::Output>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(async move {
+            self.client.preflight(self.required_permissions()).await?;
+            let mut params = self.params;
+            params.validate()?;
+            let mut r = self.client.client.post("/subscriptions");
+            r = r.json(params);
+            r = self.client.authorize(r, &self.options);
+            let res = r.await?;
+            res.json().map_err(Into::into)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::StaticToken;
+    use crate::model::SubscriptionResource;
+    use crate::MicrosoftAuth;
+
+    #[test]
+    fn test_rich_expiration() {
+        let certificate = NotificationCertificate::generate("cert");
+        let client = MicrosoftClient::builder()
+            .http_client(httpclient::Client::new())
+            .auth(MicrosoftAuth::provider(StaticToken::new("token")))
+            .build()
+            .unwrap();
+        let request = |expiration| {
+            client
+                .create_subscription(SubscriptionResource::inbox(None), "https://example.com/notify")
+                .include_resource_data(&certificate)
+                // set after include_resource_data, which used to bypass the cap
+                .expiration(expiration)
+        };
+
+        let mut params = request(Utc::now() + Duration::days(3)).lifecycle_notification_url("https://example.com/lifecycle").params;
+        params.validate().unwrap();
+        assert!(params.expiration_date_time <= Utc::now() + Duration::minutes(MAX_RICH_MESSAGE_SUBSCRIPTION_MINUTES));
+
+        let mut params = request(Utc::now() + Duration::hours(2)).params;
+        assert!(matches!(params.validate(), Err(MicrosoftError::Config(_))));
+        let mut params = request(Utc::now() + Duration::minutes(30)).params;
+        params.validate().unwrap();
+    }
+}
@@ -0,0 +1,44 @@
+use crate::auth::Scope;
+use crate::request::RequiredPermissions;
+use crate::{FluentRequest, MicrosoftClient, MicrosoftResult};
+use futures::future::BoxFuture;
+use std::future::IntoFuture;
+
+#[derive(Debug, Clone)]
+pub struct DeleteSubscriptionRequest {
+    id: String,
+}
+
+impl MicrosoftClient {
+    pub fn delete_subscription(&self, subscription_id: &str) -> FluentRequest<'_, DeleteSubscriptionRequest> {
+        FluentRequest {
+            client: self,
+            options: Default::default(),
+            params: DeleteSubscriptionRequest {
+                id: subscription_id.to_string(),
+            },
+        }
+    }
+}
+
+impl RequiredPermissions for FluentRequest<'_, DeleteSubscriptionRequest> {
+    fn required_permissions(&self) -> &'static [&'static [Scope]] {
+        &[]
+    }
+}
+
+impl<'a> IntoFuture for FluentRequest<'a, DeleteSubscriptionRequest> {
+    type Output = MicrosoftResult<()>;
+    type IntoFuture = BoxFuture<'a, Self::Output>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(async move {
+            self.client.preflight(self.required_permissions()).await?;
+            let url = format!("/subscriptions/{}", self.params.id);
+            let mut r = self.client.client.delete(url);
+            r = self.client.authorize(r, &self.options);
+            _ = r.await?;
+            Ok(())
+        })
+    }
+}
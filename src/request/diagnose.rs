@@ -0,0 +1,282 @@
+use crate::auth::{Scope, TokenClaims};
+use crate::request::{READ_MAIL, SEND_MAIL, WRITE_MAIL};
+use crate::{FluentRequest, MicrosoftClient, MicrosoftError, MicrosoftResult};
+use futures::future::BoxFuture;
+use serde::Serialize;
+use std::fmt;
+use std::future::IntoFuture;
+
+#[derive(Debug, Clone)]
+pub struct DiagnoseRequest {
+    mailbox: Option<String>,
+}
+
+impl MicrosoftClient {
+    /// Check the client's access token and mailbox, and which mail operations the token permits.
+    /// Problems are reported in the [`Diagnosis`] rather than returned as errors.
+    pub fn diagnose(&self) -> FluentRequest<'_, DiagnoseRequest> {
+        FluentRequest {
+            client: self,
+            options: Default::default(),
+            params: DiagnoseRequest { mailbox: None },
+        }
+    }
+}
+
+impl<'a> FluentRequest<'a, DiagnoseRequest> {
+    /// The mailbox to check instead of the signed in user's. App-only tokens have no signed in user, so their
+    /// mailbox is only checked if one is set.
+    pub fn mailbox(mut self, mailbox: impl Into<String>) -> Self {
+        self.params.mailbox = Some(mailbox.into());
+        self
+    }
+}
+
+/// The outcome of one check of a [`Diagnosis`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", content = "detail", rename_all = "snake_case")]
+pub enum Check {
+    Passed,
+    Failed(String),
+    /// Not run, because of an earlier failure or because it doesn't apply.
+    Skipped(String),
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Check::Passed => f.write_str("ok"),
+            Check::Failed(reason) => write!(f, "failed: {reason}"),
+            Check::Skipped(reason) => write!(f, "skipped: {reason}"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MailOperation {
+    Read,
+    Write,
+    Send,
+}
+
+impl MailOperation {
+    pub const ALL: [MailOperation; 3] = [MailOperation::Read, MailOperation::Write, MailOperation::Send];
+
+    /// The same requirements as the requests themselves, see [`crate::request::RequiredPermissions`].
+    pub fn required_permissions(&self) -> &'static [&'static [Scope]] {
+        match self {
+            MailOperation::Read => &[READ_MAIL],
+            MailOperation::Write => &[WRITE_MAIL],
+            MailOperation::Send => &[WRITE_MAIL, SEND_MAIL],
+        }
+    }
+}
+
+impl fmt::Display for MailOperation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            MailOperation::Read => "read mail",
+            MailOperation::Write => "write mail",
+            MailOperation::Send => "send mail",
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct OperationCheck {
+    pub operation: MailOperation,
+    /// `None` if the token's claims can't be read, e.g. for personal Microsoft accounts.
+    pub permitted: Option<bool>,
+    pub missing: Vec<Scope>,
+}
+
+/// What [`MicrosoftClient::diagnose`] found. Serializes to JSON, or displays as text, to attach to a support
+/// ticket. The access token itself is left out.
+#[derive(Debug, Clone, Serialize)]
+pub struct Diagnosis {
+    /// Whether a token could be obtained, and Graph accepted it.
+    pub token: Check,
+    /// `None` if no token could be obtained, or it isn't a JWT.
+    pub claims: Option<TokenClaims>,
+    /// Whether the mailbox is enabled for Exchange Online, and the token can open it.
+    pub mailbox: Check,
+    pub operations: Vec<OperationCheck>,
+}
+
+impl Diagnosis {
+    pub fn tenant_id(&self) -> Option<&str> {
+        self.claims.as_ref().and_then(|c| c.tid.as_deref())
+    }
+
+    /// Every check passed, and every operation is permitted.
+    pub fn is_healthy(&self) -> bool {
+        self.token == Check::Passed
+            && self.mailbox == Check::Passed
+            && self.operations.iter().all(|o| o.permitted != Some(false))
+    }
+}
+
+impl fmt::Display for Diagnosis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "token: {}", self.token)?;
+        if let Some(claims) = &self.claims {
+            writeln!(f, "tenant: {}", claims.tid.as_deref().unwrap_or("unknown"))?;
+            match claims.upn.as_ref().or(claims.preferred_username.as_ref()) {
+                Some(user) => writeln!(f, "user: {user}")?,
+                None => writeln!(f, "app: {}", claims.appid.as_deref().unwrap_or("unknown"))?,
+            }
+            if let Some(expires_at) = claims.expires_at() {
+                writeln!(f, "expires: {expires_at}")?;
+            }
+        }
+        writeln!(f, "mailbox: {}", self.mailbox)?;
+        for check in &self.operations {
+            match check.permitted {
+                None => writeln!(f, "{}: unknown", check.operation)?,
+                Some(true) => writeln!(f, "{}: permitted", check.operation)?,
+                Some(false) => {
+                    let missing = check.missing.iter().map(Scope::as_str).collect::<Vec<_>>().join(", ");
+                    writeln!(f, "{}: {missing} missing", check.operation)?
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl DiagnoseRequest {
+    fn url(&self) -> String {
+        let mailbox = match &self.mailbox {
+            Some(m) => format!("/users/{m}"),
+            None => "/me".to_string(),
+        };
+        // the cheapest request that needs a working mailbox
+        format!("{mailbox}/mailFolders/inbox?$select=id")
+    }
+}
+
+impl<'a> IntoFuture for FluentRequest<'a, DiagnoseRequest> {
+    type Output = MicrosoftResult<Diagnosis>;
+    type IntoFuture = BoxFuture<'a, Self::Output>;
+
+    /// Fails only if Graph can't be reached at all.
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(async move {
+            let token = match self.client.access_token().await {
+                Ok(token) => token,
+                Err(e) => {
+                    return Ok(Diagnosis {
+                        token: Check::Failed(e.to_string()),
+                        claims: None,
+                        mailbox: Check::Skipped("no access token".to_string()),
+                        operations: Vec::new(),
+                    })
+                }
+            };
+            let claims = TokenClaims::decode(&token).ok();
+            let operations = MailOperation::ALL
+                .into_iter()
+                .map(|operation| {
+                    let missing = claims.as_ref().map(|c| c.missing(operation.required_permissions()));
+                    OperationCheck {
+                        operation,
+                        permitted: missing.as_ref().map(Vec::is_empty),
+                        missing: missing.unwrap_or_default(),
+                    }
+                })
+                .collect();
+            let app_only = claims.as_ref().is_some_and(TokenClaims::is_app_only);
+            let (token, mailbox) = if app_only && self.params.mailbox.is_none() {
+                let reason = "app-only tokens have no signed in user; set a mailbox to check";
+                (Check::Skipped(reason.to_string()), Check::Skipped(reason.to_string()))
+            } else {
+                let mut r = self.client.client.get(self.params.url());
+                r = self.client.authorize(r, &self.options);
+                match r.await.map_err(MicrosoftError::from) {
+                    Ok(_) => (Check::Passed, Check::Passed),
+                    Err(e @ MicrosoftError::Auth(_)) => {
+                        (Check::Failed(e.to_string()), Check::Skipped("Graph rejected the token".to_string()))
+                    }
+                    // any other answer from Graph means the token was accepted
+                    Err(e) if e.graph_error().is_some() => (Check::Passed, Check::Failed(e.to_string())),
+                    Err(e) => return Err(e),
+                }
+            };
+            Ok(Diagnosis {
+                token,
+                claims,
+                mailbox,
+                operations,
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::ClientCredentials;
+    use crate::test_util::serve;
+    use crate::{Cloud, MicrosoftAuth};
+    use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+    use base64::Engine;
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_diagnose() {
+        let url = serve(|req| async move {
+            match req.uri().path() {
+                "/token" => {
+                    let claims = r#"{"tid":"t1","appid":"app","roles":["Mail.Read"]}"#;
+                    let token = format!("e30.{}.sig", URL_SAFE_NO_PAD.encode(claims));
+                    (200, format!(r#"{{"token_type":"Bearer","expires_in":3599,"access_token":"{token}"}}"#))
+                }
+                "/bad-token" => (
+                    401,
+                    r#"{"error":"invalid_client","error_description":"AADSTS7000215: Invalid client secret provided."}"#.to_string(),
+                ),
+                "/users/onprem@contoso.com/mailFolders/inbox" => (
+                    404,
+                    r#"{"error":{"code":"MailboxNotEnabledForRESTAPI","message":"The mailbox is either inactive, soft-deleted, or is hosted on-premise."}}"#
+                        .to_string(),
+                ),
+                _ => (200, r#"{"id":"inbox"}"#.to_string()),
+            }
+        })
+        .await;
+        let client = |token_path: &str| {
+            let auth = ClientCredentials::new(Cloud::Global, "t1", "app", "secret").token_endpoint(format!("{url}{token_path}"));
+            MicrosoftClient::builder()
+                .http_client(httpclient::Client::new())
+                .base_url(&url)
+                .auth(MicrosoftAuth::ClientCredentials {
+                    middleware: Arc::new(auth),
+                })
+                .build()
+                .unwrap()
+        };
+        let client_credentials = client("/token");
+
+        let diagnosis = client_credentials.diagnose().await.unwrap();
+        assert_eq!(diagnosis.tenant_id(), Some("t1"));
+        assert!(matches!(diagnosis.mailbox, Check::Skipped(_)));
+
+        let diagnosis = client_credentials.diagnose().mailbox("shared@contoso.com").await.unwrap();
+        assert_eq!(diagnosis.token, Check::Passed);
+        assert_eq!(diagnosis.mailbox, Check::Passed);
+        assert_eq!(diagnosis.operations[0].permitted, Some(true));
+        assert_eq!(diagnosis.operations[2].missing, vec![Scope::MailReadWrite, Scope::MailSend]);
+        assert!(!diagnosis.is_healthy());
+        let report = diagnosis.to_string();
+        assert!(report.contains("app: app") && report.contains("send mail: Mail.ReadWrite, Mail.Send missing"), "{report}");
+
+        let diagnosis = client_credentials.diagnose().mailbox("onprem@contoso.com").await.unwrap();
+        assert_eq!(diagnosis.token, Check::Passed);
+        assert!(matches!(&diagnosis.mailbox, Check::Failed(reason) if reason.starts_with("Mailbox not enabled")));
+
+        let diagnosis = client("/bad-token").diagnose().mailbox("shared@contoso.com").await.unwrap();
+        assert!(matches!(&diagnosis.token, Check::Failed(reason) if reason.contains("AADSTS7000215")), "{diagnosis}");
+        assert!(diagnosis.claims.is_none());
+    }
+}
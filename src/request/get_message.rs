@@ -1,35 +1,122 @@
-// use std::future::IntoFuture;
-// use futures::future::BoxFuture;
-// use httpclient::InMemoryResult;
-// use serde::{Deserialize, Serialize};
-// use crate::{FluentRequest, MicrosoftClient};
-
-// #[derive(Debug, Clone, Serialize, Deserialize)]
-// pub struct MessageRef {
-//
-// }
-//
-// pub struct ListMessagesRequest {
-//
-// }
-
-// impl MicrosoftClient {
-// pub async fn get_message(&self, id: &str) -> FluentRequest<ListMessagesRequest> {
-//     FluentRequest {
-//         client: self,
-//         params: vec![],
-//     }
-// }
-// }
-
-// impl<'a> IntoFuture for FluentRequest<'a, ListMessagesRequest> {
-//     type Output = InMemoryResult<Vec<MessageRef>>;
-//     type IntoFuture = BoxFuture<'a, Self::Output>;
-//
-//     fn into_future(self) -> Self::IntoFuture {
-//         Box::pin(async move {
-//             let url = &format!("/v1.0/me/messages");
-//
-//         })
-//     }
-// }
+use crate::auth::Scope;
+use crate::model::{EmailMessage, Projection};
+use crate::request::{with_query, BatchItem, Batchable, Projected, RequiredPermissions, READ_MAIL};
+use crate::{FluentRequest, MicrosoftClient, MicrosoftResult};
+use futures::future::BoxFuture;
+use httpclient::{InMemoryResponseExt, Method};
+use std::future::IntoFuture;
+
+#[derive(Debug, Clone)]
+pub struct GetMessageRequest {
+    id: String,
+    mailbox: Option<String>,
+    select: Vec<String>,
+}
+
+impl MicrosoftClient {
+    pub fn get_message(&self, id: &str) -> FluentRequest<'_, GetMessageRequest> {
+        FluentRequest {
+            client: self,
+            options: Default::default(),
+            params: GetMessageRequest {
+                id: id.to_string(),
+                mailbox: None,
+                select: Vec::new(),
+            },
+        }
+    }
+}
+
+impl<'a> FluentRequest<'a, GetMessageRequest> {
+    pub fn mailbox(mut self, mailbox: impl Into<String>) -> Self {
+        self.params.mailbox = Some(mailbox.into());
+        self
+    }
+
+    pub fn select(mut self, select: impl Into<Vec<String>>) -> Self {
+        self.params.select = select.into();
+        self
+    }
+
+    /// Read the message as `T`, selecting only its [`Projection::FIELDS`].
+    pub fn select_as<T: Projection>(mut self) -> FluentRequest<'a, Projected<GetMessageRequest, T>> {
+        self.params.select = T::FIELDS.iter().map(|f| f.to_string()).collect();
+        FluentRequest {
+            client: self.client,
+            options: self.options,
+            params: Projected::new(self.params),
+        }
+    }
+}
+
+impl GetMessageRequest {
+    fn url(&self) -> String {
+        let url = match &self.mailbox {
+            Some(m) => format!("/users/{m}/messages/{}", self.id),
+            None => format!("/me/messages/{}", self.id),
+        };
+        let mut query = Vec::new();
+        if !self.select.is_empty() {
+            query.push(("$select", self.select.join(",")));
+        }
+        with_query(url, &query)
+    }
+}
+
+impl Batchable for FluentRequest<'_, GetMessageRequest> {
+    type Output = EmailMessage;
+
+    fn to_batch_item(&self) -> BatchItem {
+        BatchItem::new(Method::GET, self.params.url())
+    }
+}
+
+impl RequiredPermissions for FluentRequest<'_, GetMessageRequest> {
+    fn required_permissions(&self) -> &'static [&'static [Scope]] {
+        &[READ_MAIL]
+    }
+}
+
+impl<'a> IntoFuture for FluentRequest<'a, GetMessageRequest> {
+    type Output = MicrosoftResult<EmailMessage>;
+    type IntoFuture = BoxFuture<'a, Self::Output>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(async move {
+            self.client.preflight(self.required_permissions()).await?;
+            let mut r = self.client.client.get(self.params.url());
+            r = self.client.authorize(r, &self.options);
+            let res = r.await?;
+            res.json().map_err(Into::into)
+        })
+    }
+}
+
+impl<T: Projection> Batchable for FluentRequest<'_, Projected<GetMessageRequest, T>> {
+    type Output = T;
+
+    fn to_batch_item(&self) -> BatchItem {
+        BatchItem::new(Method::GET, self.params.request.url())
+    }
+}
+
+impl<T> RequiredPermissions for FluentRequest<'_, Projected<GetMessageRequest, T>> {
+    fn required_permissions(&self) -> &'static [&'static [Scope]] {
+        &[READ_MAIL]
+    }
+}
+
+impl<'a, T: Projection + Send + 'a> IntoFuture for FluentRequest<'a, Projected<GetMessageRequest, T>> {
+    type Output = MicrosoftResult<T>;
+    type IntoFuture = BoxFuture<'a, Self::Output>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(async move {
+            self.client.preflight(self.required_permissions()).await?;
+            let mut r = self.client.client.get(self.params.request.url());
+            r = self.client.authorize(r, &self.options);
+            let res = r.await?;
+            res.json().map_err(Into::into)
+        })
+    }
+}
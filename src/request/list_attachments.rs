@@ -1,7 +1,9 @@
+use crate::auth::Scope;
 use crate::model::{Attachment, Page};
-use crate::{FluentRequest, MicrosoftClient};
+use crate::request::{BatchItem, Batchable, RequiredPermissions, READ_MAIL};
+use crate::{FluentRequest, MicrosoftClient, MicrosoftResult};
 use futures::future::BoxFuture;
-use httpclient::{InMemoryResponseExt, InMemoryResult};
+use httpclient::{InMemoryResponseExt, Method};
 use std::future::IntoFuture;
 
 #[derive(Debug, Clone, Default)]
@@ -18,9 +20,10 @@ impl<'a> FluentRequest<'a, ListAttachmentsRequest> {
 }
 
 impl MicrosoftClient {
-    pub fn list_attachments(&self, message_id: &str) -> FluentRequest<ListAttachmentsRequest> {
+    pub fn list_attachments(&self, message_id: &str) -> FluentRequest<'_, ListAttachmentsRequest> {
         FluentRequest {
             client: self,
+            options: Default::default(),
             params: ListAttachmentsRequest {
                 id: message_id.to_string(),
                 next: None,
@@ -29,19 +32,39 @@ impl MicrosoftClient {
     }
 }
 
+impl ListAttachmentsRequest {
+    fn url(&self) -> String {
+        if let Some(next) = &self.next {
+            next.clone()
+        } else {
+            format!("/me/messages/{}/attachments", self.id)
+        }
+    }
+}
+
+impl Batchable for FluentRequest<'_, ListAttachmentsRequest> {
+    type Output = Page<Attachment>;
+
+    fn to_batch_item(&self) -> BatchItem {
+        BatchItem::new(Method::GET, self.params.url())
+    }
+}
+
+impl RequiredPermissions for FluentRequest<'_, ListAttachmentsRequest> {
+    fn required_permissions(&self) -> &'static [&'static [Scope]] {
+        &[READ_MAIL]
+    }
+}
+
 impl<'a> IntoFuture for FluentRequest<'a, ListAttachmentsRequest> {
-    type Output = InMemoryResult<Page<Attachment>>;
+    type Output = MicrosoftResult<Page<Attachment>>;
     type IntoFuture = BoxFuture<'a, Self::Output>;
 
     fn into_future(self) -> Self::IntoFuture {
         Box::pin(async move {
-            let url = if let Some(next) = self.params.next {
-                next
-            } else {
-                format!("/me/messages/{}/attachments", self.params.id)
-            };
-            let mut r = self.client.client.get(url);
-            r = self.client.authorize(r);
+            self.client.preflight(self.required_permissions()).await?;
+            let mut r = self.client.client.get(self.params.url());
+            r = self.client.authorize(r, &self.options);
             let res = r.await?;
             res.json().map_err(Into::into)
         })
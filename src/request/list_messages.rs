@@ -1,7 +1,10 @@
-use crate::model::{EmailMessage, Page};
-use crate::{FluentRequest, MicrosoftClient};
+use crate::auth::Scope;
+use crate::model::{EmailMessage, Page, Projection, SubscriptionResource};
+use crate::request::{with_query, BatchItem, Batchable, Projected, RequiredPermissions, READ_MAIL};
+use crate::{FluentRequest, MicrosoftClient, MicrosoftResult};
+use chrono::{DateTime, SecondsFormat, Utc};
 use futures::future::BoxFuture;
-use httpclient::{InMemoryResponseExt, InMemoryResult};
+use httpclient::{InMemoryResponseExt, Method};
 use std::future::IntoFuture;
 use std_ext::default;
 
@@ -15,6 +18,9 @@ pub struct ListMessagesRequest {
     skip: Option<u32>,
     order_by: Option<String>,
     mailbox: Option<String>,
+    folder: Option<String>,
+    /// full resource path, overrides mailbox and folder
+    path: Option<String>,
 }
 
 impl MicrosoftClient {
@@ -27,12 +33,32 @@ impl MicrosoftClient {
     /// and then, modulo distributed system shenanigans (a message is delivered late while you're querying), you should be good.
     /// Example filter/query syntax:
     /// https://graph.microsoft.com/v1.0/me/messages?$filter=subject eq '{subject}' and sender/emailAddress/address eq '{sender email address}' and sentDateTime ge 2023-05-17T07:28:08Z
-    pub fn list_messages(&self) -> FluentRequest<ListMessagesRequest> {
+    pub fn list_messages(&self) -> FluentRequest<'_, ListMessagesRequest> {
         FluentRequest {
             client: self,
+            options: Default::default(),
             params: default(),
         }
     }
+
+    /// Lists the messages in a subscription's resource that were received at or after `since`, oldest first.
+    /// This is the recovery path after a `missed` or `subscriptionRemoved` lifecycle notification:
+    /// pass the receive time of the last message you processed, and page through the result.
+    pub fn catch_up(&self, resource: &SubscriptionResource, since: DateTime<Utc>) -> FluentRequest<'_, ListMessagesRequest> {
+        let mut r = self
+            .list_messages()
+            .filter(format!("receivedDateTime ge {}", since.to_rfc3339_opts(SecondsFormat::Secs, true)))
+            .order_by("receivedDateTime asc");
+        match resource {
+            SubscriptionResource::Messages { mailbox } => r.params.mailbox = mailbox.clone(),
+            SubscriptionResource::Folder { mailbox, folder } => {
+                r.params.mailbox = mailbox.clone();
+                r.params.folder = Some(folder.clone());
+            }
+            SubscriptionResource::Other(path) => r.params.path = Some(format!("/{}", path.trim_start_matches('/'))),
+        }
+        r
+    }
 }
 
 impl<'a> FluentRequest<'a, ListMessagesRequest> {
@@ -61,44 +87,153 @@ impl<'a> FluentRequest<'a, ListMessagesRequest> {
         self.params.next = Some(next.into());
         self
     }
+    /// List another user's mailbox instead of `/me`. Takes a user id or user principal name.
+    pub fn mailbox(mut self, mailbox: impl Into<String>) -> Self {
+        self.params.mailbox = Some(mailbox.into());
+        self
+    }
+    /// Only list messages in one folder. Takes a folder id or a well-known name like `inbox`.
+    pub fn folder(mut self, folder: impl Into<String>) -> Self {
+        self.params.folder = Some(folder.into());
+        self
+    }
+
+    /// Read the messages as `T`, selecting only its [`Projection::FIELDS`]. Replaces any [`Self::select`], so
+    /// call it last.
+    pub fn select_as<T: Projection>(mut self) -> FluentRequest<'a, Projected<ListMessagesRequest, T>> {
+        self.params.select = T::FIELDS.iter().map(|f| f.to_string()).collect();
+        FluentRequest {
+            client: self.client,
+            options: self.options,
+            params: Projected::new(self.params),
+        }
+    }
+}
+
+impl ListMessagesRequest {
+    fn url(&self) -> String {
+        if let Some(next) = &self.next {
+            return next.clone();
+        }
+        let owner = if let Some(m) = &self.mailbox {
+            format!("/users/{m}")
+        } else {
+            "/me".to_string()
+        };
+        let url = if let Some(path) = &self.path {
+            path.clone()
+        } else if let Some(folder) = &self.folder {
+            format!("{owner}/mailFolders/{folder}/messages")
+        } else {
+            format!("{owner}/messages")
+        };
+        let mut query = Vec::new();
+        if !self.select.is_empty() {
+            query.push(("$select", self.select.join(",")));
+        }
+        if let Some(f) = &self.filter {
+            // let filter = odata_params::filters::to_query_string(&f).unwrap();
+            query.push(("$filter", f.clone()));
+        }
+        if let Some(top) = self.top {
+            query.push(("$top", top.to_string()));
+        }
+        if let Some(skip) = self.skip {
+            query.push(("$skip", skip.to_string()));
+        }
+        if let Some(order_by) = &self.order_by {
+            query.push(("$orderby", order_by.clone()));
+        }
+        with_query(url, &query)
+    }
+}
+
+impl Batchable for FluentRequest<'_, ListMessagesRequest> {
+    type Output = Page<EmailMessage>;
+
+    fn to_batch_item(&self) -> BatchItem {
+        BatchItem::new(Method::GET, self.params.url())
+    }
+}
+
+impl RequiredPermissions for FluentRequest<'_, ListMessagesRequest> {
+    fn required_permissions(&self) -> &'static [&'static [Scope]] {
+        &[READ_MAIL]
+    }
 }
 
 impl<'a> IntoFuture for FluentRequest<'a, ListMessagesRequest> {
-    type Output = InMemoryResult<Page<EmailMessage>>;
+    type Output = MicrosoftResult<Page<EmailMessage>>;
     type IntoFuture = BoxFuture<'a, Self::Output>;
 
     fn into_future(self) -> Self::IntoFuture {
         Box::pin(async move {
-            let mut r = if let Some(next) = self.params.next {
-                self.client.client.get(next)
-            } else {
-                let url = if let Some(m) = self.params.mailbox {
-                    format!("/users/{m}/messages")
-                } else {
-                    "/me/messages".to_string()
-                };
-                let mut r = self.client.client.get(url);
-                if !self.params.select.is_empty() {
-                    r = r.query("$select", &self.params.select.join(","));
-                }
-                if let Some(f) = self.params.filter {
-                    // let filter = odata_params::filters::to_query_string(&f).unwrap();
-                    r = r.query("$filter", &f);
-                }
-                if let Some(top) = self.params.top {
-                    r = r.query("$top", &top.to_string());
-                }
-                if let Some(skip) = self.params.skip {
-                    r = r.query("$skip", &skip.to_string());
-                }
-                if let Some(order_by) = self.params.order_by {
-                    r = r.query("$orderby", &order_by);
-                }
-                r
-            };
-            r = self.client.authorize(r);
+            self.client.preflight(self.required_permissions()).await?;
+            let mut r = self.client.client.get(self.params.url());
+            r = self.client.authorize(r, &self.options);
             let res = r.await?;
             res.json().map_err(Into::into)
         })
     }
 }
+
+impl<T: Projection> Batchable for FluentRequest<'_, Projected<ListMessagesRequest, T>> {
+    type Output = Page<T>;
+
+    fn to_batch_item(&self) -> BatchItem {
+        BatchItem::new(Method::GET, self.params.request.url())
+    }
+}
+
+impl<T> RequiredPermissions for FluentRequest<'_, Projected<ListMessagesRequest, T>> {
+    fn required_permissions(&self) -> &'static [&'static [Scope]] {
+        &[READ_MAIL]
+    }
+}
+
+impl<'a, T: Projection + Send + 'a> IntoFuture for FluentRequest<'a, Projected<ListMessagesRequest, T>> {
+    type Output = MicrosoftResult<Page<T>>;
+    type IntoFuture = BoxFuture<'a, Self::Output>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(async move {
+            self.client.preflight(self.required_permissions()).await?;
+            let mut r = self.client.client.get(self.params.request.url());
+            r = self.client.authorize(r, &self.options);
+            let res = r.await?;
+            res.json().map_err(Into::into)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockGraph;
+    use chrono::TimeZone;
+    use serde_json::json;
+
+    #[tokio::test]
+    async fn test_catch_up() {
+        let graph = MockGraph::start().await;
+        let id = graph.insert_message(json!({"parentFolderId": "inbox"}));
+        graph.insert_message(json!({"parentFolderId": "sentitems"}));
+        let client = graph.client();
+        let since = Utc.with_ymd_and_hms(2024, 1, 2, 3, 4, 5).unwrap();
+
+        let resource = SubscriptionResource::inbox(Some("a@b.com".to_string()));
+        let page = client.catch_up(&resource, since).await.unwrap();
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].id, id);
+        let request = graph.requests().pop().unwrap();
+        assert_eq!(request.path, "/users/a@b.com/mailFolders/inbox/messages");
+        let query = urlencoding::decode(request.query.as_deref().unwrap()).unwrap().replace('+', " ");
+        assert!(query.contains("$filter=receivedDateTime ge 2024-01-02T03:04:05Z"), "{query}");
+        assert!(query.contains("$orderby=receivedDateTime asc"), "{query}");
+
+        client.catch_up(&SubscriptionResource::messages(None), since).await.unwrap();
+        assert_eq!(graph.requests().pop().unwrap().path, "/me/messages");
+        client.catch_up(&SubscriptionResource::Other("users/b@c.com/messages".to_string()), since).await.unwrap();
+        assert_eq!(graph.requests().pop().unwrap().path, "/users/b@c.com/messages");
+    }
+}
@@ -0,0 +1,54 @@
+use crate::auth::Scope;
+use crate::model::{Page, Subscription};
+use crate::request::RequiredPermissions;
+use crate::{FluentRequest, MicrosoftClient, MicrosoftResult};
+use futures::future::BoxFuture;
+use httpclient::InMemoryResponseExt;
+use std::future::IntoFuture;
+use std_ext::default;
+
+#[derive(Debug, Clone, Default)]
+pub struct ListSubscriptionsRequest {
+    next: Option<String>,
+}
+
+impl MicrosoftClient {
+    /// Lists the subscriptions visible to the caller. With delegated auth that's the signed in user's subscriptions,
+    /// with app-only auth it's every subscription the application created.
+    pub fn list_subscriptions(&self) -> FluentRequest<'_, ListSubscriptionsRequest> {
+        FluentRequest {
+            client: self,
+            options: Default::default(),
+            params: default(),
+        }
+    }
+}
+
+impl<'a> FluentRequest<'a, ListSubscriptionsRequest> {
+    pub fn next(mut self, next: impl Into<String>) -> Self {
+        self.params.next = Some(next.into());
+        self
+    }
+}
+
+impl RequiredPermissions for FluentRequest<'_, ListSubscriptionsRequest> {
+    fn required_permissions(&self) -> &'static [&'static [Scope]] {
+        &[]
+    }
+}
+
+impl<'a> IntoFuture for FluentRequest<'a, ListSubscriptionsRequest> {
+    type Output = MicrosoftResult<Page<Subscription>>;
+    type IntoFuture = BoxFuture<'a, Self::Output>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(async move {
+            self.client.preflight(self.required_permissions()).await?;
+            let url = self.params.next.unwrap_or_else(|| "/subscriptions".to_string());
+            let mut r = self.client.client.get(url);
+            r = self.client.authorize(r, &self.options);
+            let res = r.await?;
+            res.json().map_err(Into::into)
+        })
+    }
+}
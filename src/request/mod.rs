@@ -1,8 +1,73 @@
+mod batch;
+mod create_subscription;
+mod delete_subscription;
+mod diagnose;
 mod get_message;
 mod list_attachments;
 mod list_messages;
+mod list_subscriptions;
+mod reauthorize_subscription;
+mod renew_subscription;
 mod send_email;
+mod update_message;
 
-pub use list_messages::*;
-// pub use get_message::*;
+pub use batch::*;
+pub use create_subscription::*;
+pub use delete_subscription::*;
+pub use diagnose::*;
+pub use get_message::*;
 pub use list_attachments::*;
+pub use list_messages::*;
+pub use list_subscriptions::*;
+pub use reauthorize_subscription::*;
+pub use renew_subscription::*;
+pub use update_message::*;
+
+use crate::auth::Scope;
+use std::marker::PhantomData;
+
+/// Reading mail in any mailbox the token can access. Write access implies read.
+pub(crate) const READ_MAIL: &[Scope] = &[
+    Scope::MailRead,
+    Scope::MailReadShared,
+    Scope::MailReadWrite,
+    Scope::MailReadWriteShared,
+];
+pub(crate) const WRITE_MAIL: &[Scope] = &[Scope::MailReadWrite, Scope::MailReadWriteShared];
+pub(crate) const SEND_MAIL: &[Scope] = &[Scope::MailSend, Scope::MailSendShared];
+
+/// The permissions a request needs, checked by [`crate::MicrosoftClient::check_permissions`] and the
+/// [`crate::MicrosoftClientBuilder::preflight`]. Application permissions have the same names as delegated ones.
+pub trait RequiredPermissions {
+    /// Every entry must be granted, by any one of its scopes. Scopes are listed least privileged first.
+    fn required_permissions(&self) -> &'static [&'static [Scope]];
+}
+
+/// A request whose result is read as the [`crate::model::Projection`] `T`, see `select_as`.
+#[derive(Debug, Clone)]
+pub struct Projected<R, T> {
+    pub request: R,
+    projection: PhantomData<fn() -> T>,
+}
+
+impl<R, T> Projected<R, T> {
+    pub(crate) fn new(request: R) -> Self {
+        Self {
+            request,
+            projection: PhantomData,
+        }
+    }
+}
+
+/// Append OData query options to a url. Keys are left unencoded, as they appear in Graph's documentation.
+pub(crate) fn with_query(url: String, query: &[(&str, String)]) -> String {
+    if query.is_empty() {
+        return url;
+    }
+    let query = query
+        .iter()
+        .map(|(k, v)| format!("{k}={}", urlencoding::encode(v)))
+        .collect::<Vec<_>>()
+        .join("&");
+    format!("{url}?{query}")
+}
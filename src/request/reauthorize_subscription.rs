@@ -0,0 +1,46 @@
+use crate::auth::Scope;
+use crate::request::{RequiredPermissions, READ_MAIL};
+use crate::{FluentRequest, MicrosoftClient, MicrosoftResult};
+use futures::future::BoxFuture;
+use std::future::IntoFuture;
+
+#[derive(Debug, Clone)]
+pub struct ReauthorizeSubscriptionRequest {
+    id: String,
+}
+
+impl MicrosoftClient {
+    /// Answers a `reauthorizationRequired` lifecycle notification. The client's current credentials are attached
+    /// to the subscription, so make sure they are fresh.
+    pub fn reauthorize_subscription(&self, subscription_id: &str) -> FluentRequest<'_, ReauthorizeSubscriptionRequest> {
+        FluentRequest {
+            client: self,
+            options: Default::default(),
+            params: ReauthorizeSubscriptionRequest {
+                id: subscription_id.to_string(),
+            },
+        }
+    }
+}
+
+impl RequiredPermissions for FluentRequest<'_, ReauthorizeSubscriptionRequest> {
+    fn required_permissions(&self) -> &'static [&'static [Scope]] {
+        &[READ_MAIL]
+    }
+}
+
+impl<'a> IntoFuture for FluentRequest<'a, ReauthorizeSubscriptionRequest> {
+    type Output = MicrosoftResult<()>;
+    type IntoFuture = BoxFuture<'a, Self::Output>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(async move {
+            self.client.preflight(self.required_permissions()).await?;
+            let url = format!("/subscriptions/{}/reauthorize", self.params.id);
+            let mut r = self.client.client.post(url);
+            r = self.client.authorize(r, &self.options);
+            _ = r.await?;
+            Ok(())
+        })
+    }
+}
@@ -0,0 +1,61 @@
+use crate::auth::Scope;
+use crate::model::{max_message_subscription_expiration, Subscription};
+use crate::request::{RequiredPermissions, READ_MAIL};
+use crate::{FluentRequest, MicrosoftClient, MicrosoftResult};
+use chrono::{DateTime, Utc};
+use futures::future::BoxFuture;
+use httpclient::InMemoryResponseExt;
+use serde::Serialize;
+use std::future::IntoFuture;
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenewSubscriptionRequest {
+    #[serde(skip)]
+    id: String,
+    expiration_date_time: DateTime<Utc>,
+}
+
+impl MicrosoftClient {
+    /// Extends a subscription's expiration. Defaults to the latest expiration Graph allows for messages.
+    pub fn renew_subscription(&self, subscription_id: &str) -> FluentRequest<'_, RenewSubscriptionRequest> {
+        FluentRequest {
+            client: self,
+            options: Default::default(),
+            params: RenewSubscriptionRequest {
+                id: subscription_id.to_string(),
+                expiration_date_time: max_message_subscription_expiration(),
+            },
+        }
+    }
+}
+
+impl<'a> FluentRequest<'a, RenewSubscriptionRequest> {
+    pub fn expiration(mut self, expiration: DateTime<Utc>) -> Self {
+        self.params.expiration_date_time = expiration;
+        self
+    }
+}
+
+impl RequiredPermissions for FluentRequest<'_, RenewSubscriptionRequest> {
+    fn required_permissions(&self) -> &'static [&'static [Scope]] {
+        &[READ_MAIL]
+    }
+}
+
+impl<'a> IntoFuture for FluentRequest<'a, RenewSubscriptionRequest> {
+    type Output = MicrosoftResult<Subscription>;
+    type IntoFuture = BoxFuture<'a, Self::Output>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(async move {
+            self.client.preflight(self.required_permissions()).await?;
+            let url = format!("/subscriptions/{}", self.params.id);
+            let mut r = self.client.client.patch(url);
+            r = r.json(self.params);
+            r = self.client.authorize(r, &self.options);
+            let res = r.await?;
+            res.json().map_err(Into::into)
+        })
+    }
+}
@@ -1,32 +1,19 @@
+use crate::auth::Scope;
 use crate::model::{Body, BodyType, EmailMessage, Recipient};
-use crate::{FluentRequest, MicrosoftClient};
+use crate::request::{RequiredPermissions, SEND_MAIL, WRITE_MAIL};
+use crate::{FluentRequest, MicrosoftClient, MicrosoftError, MicrosoftResult, RequestOptions};
 use base64::engine::Engine;
 use base64::prelude::BASE64_STANDARD;
 use email::Email;
 use file::File;
 use futures::future::BoxFuture;
 use html_escape::encode_text;
-use httpclient::{InMemoryResponseExt, InMemoryResult, ProtocolError, Retry};
-use serde::{de::Error, Deserialize, Serialize};
+use httpclient::InMemoryResponseExt;
+use serde::{Deserialize, Serialize};
 use std::future::IntoFuture;
-use std::sync::{Arc, LazyLock};
-use std::time::Duration;
+use std::sync::Arc;
 use std_ext::VecExt;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "lowercase")]
-enum Bool {
-    True,
-    False,
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[serde(rename_all = "camelCase")]
-struct SendEmailRequestBody {
-    pub message: SendEmailRequestMessage,
-    pub save_to_sent_items: Bool,
-}
-
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(rename_all = "camelCase")]
 struct SendEmailRequestMessage {
@@ -83,43 +70,42 @@ impl From<File> for SendEmailRequestAttachment {
 }
 
 impl MicrosoftClient {
-    pub fn send_email(&self, email: Email) -> FluentRequest<Email> {
+    pub fn send_email(&self, email: Email) -> FluentRequest<'_, Email> {
         FluentRequest {
             client: self,
+            options: Default::default(),
             params: email,
         }
     }
 }
 
-static RETRY: LazyLock<Arc<Retry>> = LazyLock::new(|| {
-    Arc::new(
-        Retry::new()
-            .backoff_delay(Duration::from_secs(1))
-            .max_retries(10)
-            .retry_codes(vec![429, 408, 425, 404]),
-    )
-});
+impl RequiredPermissions for FluentRequest<'_, Email> {
+    fn required_permissions(&self) -> &'static [&'static [Scope]] {
+        &[WRITE_MAIL, SEND_MAIL]
+    }
+}
 
 impl<'a> IntoFuture for FluentRequest<'a, Email> {
-    type Output = InMemoryResult<EmailMessage>;
+    type Output = MicrosoftResult<EmailMessage>;
     type IntoFuture = BoxFuture<'a, Self::Output>;
 
     fn into_future(self) -> Self::IntoFuture {
         use crate::model::Body as ModelBody;
         use ::email::Body;
         Box::pin(async move {
+            self.client.preflight(self.required_permissions()).await?;
             let attachments = self.params.attachments.recollect();
             let email_message: EmailMessage = if let Some(id) = &self.params.reply_to_message_id {
                 let url = format!("/me/messages/{id}/createReply", id = id);
                 let mut draft = self.client.client.post(url);
-                draft = self.client.authorize(draft);
+                draft = self.client.authorize(draft, &self.options);
                 let mut draft: EmailMessage = draft.await?.json()?;
                 // upload any attachments
                 let attachment_upload_url =
                     format!("/me/messages/{id}/attachments", id = &draft.id);
                 for attachment in attachments {
                     let mut r = self.client.client.post(&attachment_upload_url);
-                    r = self.client.authorize(r);
+                    r = self.client.authorize(r, &self.options);
                     r = r.json(attachment);
                     _ = r.await?;
                 }
@@ -132,24 +118,26 @@ impl<'a> IntoFuture for FluentRequest<'a, Email> {
                 };
                 let tag = "<body>";
                 let Some(idx) = draft.body.content.find(tag) else {
-                    return Err(httpclient::InMemoryError::Protocol(
-                        ProtocolError::JsonError(serde_json::Error::custom("no body tag")),
-                    ));
+                    return Err(MicrosoftError::UnexpectedResponse(format!(
+                        "reply draft {} has no <body> tag",
+                        draft.id
+                    )));
                 };
                 let idx = idx + tag.len();
                 draft.body.content.insert_str(idx, &body);
-                let mut data = PatchEmailRequestMessage::default();
-                data.body = Some(ModelBody {
-                    content_type: BodyType::Html,
-                    content: draft.body.content,
-                });
-                data.to_recipients = self.params.to.recollect();
-                data.cc_recipients = self.params.cc.recollect();
-                data.bcc_recipients = self.params.bcc.recollect();
+                let data = PatchEmailRequestMessage {
+                    body: Some(ModelBody {
+                        content_type: BodyType::Html,
+                        content: draft.body.content,
+                    }),
+                    to_recipients: self.params.to.recollect(),
+                    cc_recipients: self.params.cc.recollect(),
+                    bcc_recipients: self.params.bcc.recollect(),
+                };
                 let url = format!("/me/messages/{id}", id = &draft.id);
                 // request the damn thing
                 let mut r = self.client.client.patch(url);
-                r = self.client.authorize(r);
+                r = self.client.authorize(r, &self.options);
                 r = r.json(data);
                 let res = r.await?;
                 res.json()?
@@ -181,16 +169,22 @@ impl<'a> IntoFuture for FluentRequest<'a, Email> {
                     attachments,
                 };
                 r = r.json(body);
-                r = self.client.authorize(r);
+                r = self.client.authorize(r, &self.options);
                 // for this to work, we need to set Prefer Immutable IDs, but we're already setting it at the lib level.
                 // see https://learn.microsoft.com/en-us/graph/outlook-immutable-id
                 let res = r.await?;
                 res.json()?
             };
             let url = format!("/me/messages/{id}/send", id = &email_message.id);
+            // a new draft can take a moment to become visible to /send. Retrying the 404 is safe, since nothing was sent.
+            // Other failures are not retried beyond the policy, as the mail might have gone out.
+            let retry = self.options.retry.as_deref().unwrap_or(&self.client.retry);
+            let options = RequestOptions {
+                retry: Some(Arc::new(retry.clone().also_retry_non_idempotent(404))),
+                ..self.options.clone()
+            };
             let mut r = self.client.client.post(url);
-            r = self.client.authorize(r);
-            r.middlewares.insert(0, RETRY.clone());
+            r = self.client.authorize(r, &options);
             _ = r.await?;
             Ok(email_message)
         })
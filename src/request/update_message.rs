@@ -0,0 +1,113 @@
+use crate::auth::Scope;
+use crate::model::{EmailMessage, Flag};
+use crate::request::{BatchItem, Batchable, RequiredPermissions, WRITE_MAIL};
+use crate::{FluentRequest, MicrosoftClient, MicrosoftResult};
+use futures::future::BoxFuture;
+use httpclient::{InMemoryResponseExt, Method};
+use serde::Serialize;
+use std::future::IntoFuture;
+
+#[derive(Debug, Clone, Serialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct UpdateMessageBody {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    is_read: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    categories: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    importance: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    flag: Option<Flag>,
+}
+
+#[derive(Debug, Clone)]
+pub struct UpdateMessageRequest {
+    id: String,
+    mailbox: Option<String>,
+    body: UpdateMessageBody,
+}
+
+impl MicrosoftClient {
+    /// PATCH a message. Only the properties that were set are sent.
+    pub fn update_message(&self, id: &str) -> FluentRequest<'_, UpdateMessageRequest> {
+        FluentRequest {
+            client: self,
+            options: Default::default(),
+            params: UpdateMessageRequest {
+                id: id.to_string(),
+                mailbox: None,
+                body: UpdateMessageBody::default(),
+            },
+        }
+    }
+}
+
+impl<'a> FluentRequest<'a, UpdateMessageRequest> {
+    pub fn mailbox(mut self, mailbox: impl Into<String>) -> Self {
+        self.params.mailbox = Some(mailbox.into());
+        self
+    }
+
+    pub fn is_read(mut self, is_read: bool) -> Self {
+        self.params.body.is_read = Some(is_read);
+        self
+    }
+
+    pub fn categories(mut self, categories: impl Into<Vec<String>>) -> Self {
+        self.params.body.categories = Some(categories.into());
+        self
+    }
+
+    /// `low`, `normal` or `high`
+    pub fn importance(mut self, importance: impl Into<String>) -> Self {
+        self.params.body.importance = Some(importance.into());
+        self
+    }
+
+    /// `notFlagged`, `complete` or `flagged`
+    pub fn flag_status(mut self, flag_status: impl Into<String>) -> Self {
+        self.params.body.flag = Some(Flag {
+            flag_status: flag_status.into(),
+        });
+        self
+    }
+}
+
+impl UpdateMessageRequest {
+    fn url(&self) -> String {
+        match &self.mailbox {
+            Some(m) => format!("/users/{m}/messages/{}", self.id),
+            None => format!("/me/messages/{}", self.id),
+        }
+    }
+}
+
+impl Batchable for FluentRequest<'_, UpdateMessageRequest> {
+    type Output = EmailMessage;
+
+    fn to_batch_item(&self) -> BatchItem {
+        BatchItem::new(Method::PATCH, self.params.url()).json(&self.params.body)
+    }
+}
+
+impl RequiredPermissions for FluentRequest<'_, UpdateMessageRequest> {
+    fn required_permissions(&self) -> &'static [&'static [Scope]] {
+        &[WRITE_MAIL]
+    }
+}
+
+impl<'a> IntoFuture for FluentRequest<'a, UpdateMessageRequest> {
+    type Output = MicrosoftResult<EmailMessage>;
+    type IntoFuture = BoxFuture<'a, Self::Output>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(async move {
+            self.client.preflight(self.required_permissions()).await?;
+            let mut r = self.client.client.patch(self.params.url());
+            r = r.json(self.params.body);
+            r = self.client.authorize(r, &self.options);
+            let res = r.await?;
+            res.json().map_err(Into::into)
+        })
+    }
+}
@@ -0,0 +1,285 @@
+//! Retrying of failed requests, most importantly Graph throttling.
+//!
+//! Exchange throttles per mailbox and per app, answering with 429 (or 503/504 under load) and a `Retry-After`
+//! header. [`RetryPolicy`] waits as instructed and retries, so bulk jobs slow down instead of failing.
+//! see https://learn.microsoft.com/en-us/graph/throttling
+//!
+//! Requests with side effects (POST, PATCH) are only retried on statuses that guarantee nothing happened,
+//! because a timed out `/send` may well have sent the mail.
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use httpclient::{header, InMemoryRequest, Method, Middleware, Next, ProtocolResult, Response, StatusCode};
+use rand::Rng;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Reported to [`RetryPolicy::on_retry`] before every retry, whether Graph throttled the request or failed it.
+#[derive(Debug, Clone)]
+pub struct RetryEvent {
+    pub method: Method,
+    pub url: String,
+    pub status: StatusCode,
+    /// 1 for the first retry.
+    pub attempt: usize,
+    /// How long we wait before retrying.
+    pub delay: Duration,
+    /// The wait Graph asked for, if it sent `Retry-After`.
+    pub retry_after: Option<Duration>,
+}
+
+impl RetryEvent {
+    /// Whether Graph throttled the request, rather than failing it.
+    pub fn is_throttle(&self) -> bool {
+        self.status == StatusCode::TOO_MANY_REQUESTS
+            || (self.status == StatusCode::SERVICE_UNAVAILABLE && self.retry_after.is_some())
+    }
+}
+
+type RetryCallback = Arc<dyn Fn(&RetryEvent) + Send + Sync>;
+
+/// Statuses worth retrying for idempotent requests (GET, PUT, DELETE, ...).
+pub const DEFAULT_RETRY_CODES: &[u16] = &[429, 500, 502, 503, 504];
+
+/// Statuses worth retrying for non-idempotent requests. Graph rejects throttled requests before processing them.
+pub const DEFAULT_NON_IDEMPOTENT_RETRY_CODES: &[u16] = &[429];
+
+#[derive(Clone)]
+pub struct RetryPolicy {
+    max_retries: usize,
+    retry_codes: Vec<u16>,
+    non_idempotent_retry_codes: Vec<u16>,
+    base_delay: Duration,
+    max_delay: Duration,
+    max_total_wait: Duration,
+    on_retry: Option<RetryCallback>,
+}
+
+impl fmt::Debug for RetryPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RetryPolicy")
+            .field("max_retries", &self.max_retries)
+            .field("retry_codes", &self.retry_codes)
+            .field("non_idempotent_retry_codes", &self.non_idempotent_retry_codes)
+            .field("base_delay", &self.base_delay)
+            .field("max_delay", &self.max_delay)
+            .field("max_total_wait", &self.max_total_wait)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_retries: 5,
+            retry_codes: DEFAULT_RETRY_CODES.to_vec(),
+            non_idempotent_retry_codes: DEFAULT_NON_IDEMPOTENT_RETRY_CODES.to_vec(),
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(60),
+            max_total_wait: Duration::from_secs(120),
+            on_retry: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Never retry.
+    pub fn none() -> Self {
+        Self::default().max_retries(0)
+    }
+
+    pub fn max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Statuses to retry for idempotent methods. Defaults to [`DEFAULT_RETRY_CODES`].
+    pub fn retry_codes(mut self, codes: impl Into<Vec<u16>>) -> Self {
+        self.retry_codes = codes.into();
+        self
+    }
+
+    /// Statuses to retry for POST and PATCH. Defaults to [`DEFAULT_NON_IDEMPOTENT_RETRY_CODES`].
+    /// Only add statuses for which you know the request had no effect.
+    pub fn non_idempotent_retry_codes(mut self, codes: impl Into<Vec<u16>>) -> Self {
+        self.non_idempotent_retry_codes = codes.into();
+        self
+    }
+
+    /// The first backoff when Graph doesn't send `Retry-After`. Doubles on every attempt.
+    pub fn base_delay(mut self, delay: Duration) -> Self {
+        self.base_delay = delay;
+        self
+    }
+
+    /// Upper bound for a single wait, including ones requested with `Retry-After`.
+    pub fn max_delay(mut self, delay: Duration) -> Self {
+        self.max_delay = delay;
+        self
+    }
+
+    /// Give up, returning the throttled response, rather than wait longer than this in total.
+    pub fn max_total_wait(mut self, wait: Duration) -> Self {
+        self.max_total_wait = wait;
+        self
+    }
+
+    /// Called before every retry, e.g. to log or count throttling with [`RetryEvent::is_throttle`].
+    pub fn on_retry(mut self, callback: impl Fn(&RetryEvent) + Send + Sync + 'static) -> Self {
+        self.on_retry = Some(Arc::new(callback));
+        self
+    }
+
+    pub fn should_retry(&self, method: &Method, status: StatusCode) -> bool {
+        let codes = if method.is_idempotent() {
+            &self.retry_codes
+        } else {
+            &self.non_idempotent_retry_codes
+        };
+        codes.contains(&status.as_u16())
+    }
+
+    pub(crate) fn also_retry_non_idempotent(mut self, code: u16) -> Self {
+        if !self.non_idempotent_retry_codes.contains(&code) {
+            self.non_idempotent_retry_codes.push(code);
+        }
+        self
+    }
+
+    /// Exponential backoff with jitter, so clients throttled together don't retry together.
+    fn backoff(&self, attempt: usize) -> Duration {
+        let exp = self.base_delay.saturating_mul(1 << attempt.saturating_sub(1).min(16));
+        let delay = exp.min(self.max_delay);
+        delay.mul_f64(rand::thread_rng().gen_range(0.5..=1.0))
+    }
+}
+
+/// `Retry-After` is either a number of seconds or an HTTP date.
+pub(crate) fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    Some((date.with_timezone(&Utc) - Utc::now()).to_std().unwrap_or_default())
+}
+
+#[async_trait]
+impl Middleware for RetryPolicy {
+    async fn handle(&self, request: InMemoryRequest, next: Next<'_>) -> ProtocolResult<Response> {
+        let mut waited = Duration::ZERO;
+        let mut attempt = 0;
+        loop {
+            let res = next.run(request.clone()).await?;
+            let status = res.status();
+            if !self.should_retry(request.method(), status) || attempt >= self.max_retries {
+                return Ok(res);
+            }
+            attempt += 1;
+            let retry_after = res
+                .headers()
+                .get(header::RETRY_AFTER)
+                .and_then(|v| v.to_str().ok())
+                .and_then(parse_retry_after);
+            let delay = retry_after.map_or_else(|| self.backoff(attempt), |d| d.min(self.max_delay));
+            if waited + delay > self.max_total_wait {
+                return Ok(res);
+            }
+            if let Some(cb) = &self.on_retry {
+                cb(&RetryEvent {
+                    method: request.method().clone(),
+                    url: request.uri().to_string(),
+                    status,
+                    attempt,
+                    delay,
+                    retry_after,
+                });
+            }
+            tokio::time::sleep(delay).await;
+            waited += delay;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::serve_http;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    #[test]
+    fn test_parse_retry_after() {
+        assert_eq!(parse_retry_after("3"), Some(Duration::from_secs(3)));
+        assert_eq!(parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"), Some(Duration::ZERO));
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[test]
+    fn test_backoff() {
+        let policy = RetryPolicy::new().base_delay(Duration::from_secs(1)).max_delay(Duration::from_secs(5));
+        let delay = policy.backoff(2);
+        assert!(delay >= Duration::from_secs(1) && delay <= Duration::from_secs(2));
+        assert!(policy.backoff(10) <= Duration::from_secs(5));
+    }
+
+    #[test]
+    fn test_should_retry() {
+        let policy = RetryPolicy::new();
+        assert!(policy.should_retry(&Method::GET, StatusCode::GATEWAY_TIMEOUT));
+        assert!(policy.should_retry(&Method::POST, StatusCode::TOO_MANY_REQUESTS));
+        assert!(!policy.should_retry(&Method::POST, StatusCode::GATEWAY_TIMEOUT));
+        assert!(!policy.should_retry(&Method::GET, StatusCode::NOT_FOUND));
+    }
+
+    #[test]
+    fn test_is_throttle() {
+        let event = |status: StatusCode, retry_after: Option<Duration>| RetryEvent {
+            method: Method::GET,
+            url: "/me".to_string(),
+            status,
+            attempt: 1,
+            delay: Duration::ZERO,
+            retry_after,
+        };
+        assert!(event(StatusCode::TOO_MANY_REQUESTS, None).is_throttle());
+        assert!(event(StatusCode::SERVICE_UNAVAILABLE, Some(Duration::ZERO)).is_throttle());
+        assert!(!event(StatusCode::SERVICE_UNAVAILABLE, None).is_throttle());
+        assert!(!event(StatusCode::BAD_GATEWAY, None).is_throttle());
+    }
+
+    #[tokio::test]
+    async fn test_retry_after() {
+        let hits = Arc::new(AtomicUsize::new(0));
+        let server_hits = hits.clone();
+        let url = serve_http(move |_req| {
+            let n = server_hits.fetch_add(1, Ordering::SeqCst);
+            async move {
+                let status = if n < 2 { 429 } else { 200 };
+                hyper::Response::builder().status(status).header("Retry-After", "0").body("{}".to_string()).unwrap()
+            }
+        })
+        .await;
+
+        let events = Arc::new(AtomicUsize::new(0));
+        let counter = events.clone();
+        let policy = RetryPolicy::new().on_retry(move |e| {
+            assert_eq!(e.retry_after, Some(Duration::ZERO));
+            assert!(e.is_throttle());
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        let client = httpclient::Client::new().with_middleware(policy);
+        let res = client.get(format!("{url}/me")).await.unwrap();
+        assert_eq!(res.status(), StatusCode::OK);
+        assert_eq!(hits.load(Ordering::SeqCst), 3);
+        assert_eq!(events.load(Ordering::SeqCst), 2);
+
+        let client = httpclient::Client::new().with_middleware(RetryPolicy::none());
+        hits.store(0, Ordering::SeqCst);
+        let err = client.get(format!("{url}/me")).await.unwrap_err();
+        assert_eq!(err.status(), Some(StatusCode::TOO_MANY_REQUESTS));
+    }
+}
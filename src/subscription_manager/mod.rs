@@ -0,0 +1,695 @@
+//! Keeps a set of change notification subscriptions alive in the background.
+//!
+//! Graph deletes a subscription once its expiration passes, and doesn't tell anyone about it.
+//! The [`SubscriptionManager`] periodically reconciles the subscriptions you want against the ones Graph
+//! has, renewing them before they expire and recreating any that were deleted or lost.
+use crate::encryption::NotificationCertificate;
+use crate::model::{
+    ChangeType, LifecycleEvent, LifecycleNotification, Subscription, SubscriptionResource, MAX_MESSAGE_SUBSCRIPTION_MINUTES, MAX_RICH_MESSAGE_SUBSCRIPTION_MINUTES,
+};
+use crate::{MicrosoftClient, MicrosoftError, MicrosoftResult};
+use chrono::{Duration, Utc};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, Mutex};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver};
+use tokio::sync::Notify;
+use tokio::task::JoinHandle;
+
+mod store;
+
+pub use store::*;
+
+/// A subscription the manager should keep alive.
+#[derive(Debug, Clone)]
+pub struct SubscriptionSpec {
+    pub resource: SubscriptionResource,
+    pub notification_url: String,
+    pub lifecycle_notification_url: Option<String>,
+    pub change_types: Vec<ChangeType>,
+    pub client_state: Option<String>,
+    /// How far out to set the expiration on create and renew.
+    pub lifetime: Duration,
+    /// Set for rich notifications. See [`crate::request::CreateSubscriptionRequest`].
+    pub encryption_certificate: Option<Arc<NotificationCertificate>>,
+    pub select: Vec<String>,
+}
+
+impl SubscriptionSpec {
+    pub fn new(resource: SubscriptionResource, notification_url: impl Into<String>) -> Self {
+        Self {
+            resource,
+            notification_url: notification_url.into(),
+            lifecycle_notification_url: None,
+            change_types: vec![ChangeType::Created],
+            client_state: None,
+            lifetime: Duration::minutes(MAX_MESSAGE_SUBSCRIPTION_MINUTES),
+            encryption_certificate: None,
+            select: Vec::new(),
+        }
+    }
+
+    pub fn change_types(mut self, change_types: impl Into<Vec<ChangeType>>) -> Self {
+        self.change_types = change_types.into();
+        self
+    }
+
+    pub fn client_state(mut self, client_state: impl Into<String>) -> Self {
+        self.client_state = Some(client_state.into());
+        self
+    }
+
+    pub fn lifecycle_notification_url(mut self, url: impl Into<String>) -> Self {
+        self.lifecycle_notification_url = Some(url.into());
+        self
+    }
+
+    pub fn lifetime(mut self, lifetime: Duration) -> Self {
+        self.lifetime = lifetime;
+        self
+    }
+
+    /// Include the encrypted message in each notification. Caps the lifetime at the one day Graph allows for these.
+    /// Lifetimes over an hour also need a [`Self::lifecycle_notification_url`].
+    pub fn include_resource_data(mut self, certificate: Arc<NotificationCertificate>) -> Self {
+        self.encryption_certificate = Some(certificate);
+        self.lifetime = self.lifetime.min(Duration::minutes(MAX_RICH_MESSAGE_SUBSCRIPTION_MINUTES));
+        self
+    }
+
+    pub fn select(mut self, select: impl Into<Vec<String>>) -> Self {
+        self.select = select.into();
+        self
+    }
+
+    /// Identifies the spec in the [`SubscriptionStore`]. One subscription is kept per resource, notification url and
+    /// set of change types, so e.g. a staging and a production endpoint can watch the same mailbox.
+    pub fn key(&self) -> String {
+        format!("{} {} {}", self.resource, self.notification_url, change_types_key(&self.change_types))
+    }
+
+    /// Whether an existing Graph subscription already fulfills this spec.
+    fn matches(&self, subscription: &Subscription) -> bool {
+        fn normalize(resource: &str) -> String {
+            let path = resource.split_once('?').map_or(resource, |(path, _)| path);
+            path.trim_start_matches('/').to_lowercase()
+        }
+        normalize(&subscription.resource) == normalize(&self.resource.to_string())
+            && subscription.notification_url == self.notification_url
+            && change_types_key(&subscription.change_type) == change_types_key(&self.change_types)
+    }
+}
+
+/// The change types sorted and deduplicated, so the order they're listed in doesn't matter.
+fn change_types_key(change_types: &[ChangeType]) -> String {
+    let mut names: Vec<String> = change_types.iter().map(ChangeType::to_string).collect();
+    names.sort();
+    names.dedup();
+    names.join(",")
+}
+
+#[derive(Debug)]
+pub enum SubscriptionEvent {
+    Created { key: String, subscription: Subscription },
+    /// A subscription that already existed in Graph, but not in the store, was taken over instead of creating a duplicate.
+    Adopted { key: String, subscription: Subscription },
+    Renewed { key: String, subscription: Subscription },
+    /// The previous subscription expired, was deleted, or otherwise disappeared from Graph, and was replaced.
+    Recreated {
+        key: String,
+        previous_id: String,
+        subscription: Subscription,
+    },
+    /// Answered a `reauthorizationRequired` lifecycle notification.
+    Reauthorized { key: String, subscription_id: String },
+    /// Graph dropped notifications for this subscription. Catch up from your last checkpoint with
+    /// [`MicrosoftClient::catch_up`]. Also sent after a removed subscription was recreated.
+    Missed {
+        key: String,
+        resource: SubscriptionResource,
+        subscription_id: String,
+    },
+    /// The spec was unwatched, and its subscription deleted.
+    Removed { key: String, subscription_id: String },
+    /// `key` is `None` when the failure isn't specific to one subscription, e.g. listing subscriptions failed.
+    Failed {
+        key: Option<String>,
+        error: SubscriptionManagerError,
+    },
+}
+
+#[derive(Debug)]
+pub enum SubscriptionManagerError {
+    Graph(MicrosoftError),
+    Store(StoreError),
+    /// A lifecycle notification referred to a subscription this manager doesn't maintain.
+    UnknownSubscription(String),
+}
+
+impl fmt::Display for SubscriptionManagerError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SubscriptionManagerError::Graph(e) => write!(f, "Graph request failed: {e}"),
+            SubscriptionManagerError::Store(e) => write!(f, "Subscription store failed: {e}"),
+            SubscriptionManagerError::UnknownSubscription(id) => write!(f, "Unknown subscription: {id}"),
+        }
+    }
+}
+
+impl std::error::Error for SubscriptionManagerError {}
+
+impl From<MicrosoftError> for SubscriptionManagerError {
+    fn from(value: MicrosoftError) -> Self {
+        SubscriptionManagerError::Graph(value)
+    }
+}
+
+pub struct SubscriptionManager {
+    client: Arc<MicrosoftClient>,
+    store: Arc<dyn SubscriptionStore>,
+    specs: Mutex<HashMap<String, SubscriptionSpec>>,
+    renewal_margin: Duration,
+    check_interval: std::time::Duration,
+    wake: Notify,
+    // held by `sync` and `handle_lifecycle`, so they never both see a subscription missing and both create one
+    reconciling: tokio::sync::Mutex<()>,
+}
+
+impl SubscriptionManager {
+    pub fn new(client: Arc<MicrosoftClient>, store: Arc<dyn SubscriptionStore>) -> Self {
+        Self {
+            client,
+            store,
+            specs: Mutex::new(HashMap::new()),
+            renewal_margin: Duration::hours(12),
+            check_interval: std::time::Duration::from_secs(10 * 60),
+            wake: Notify::new(),
+            reconciling: tokio::sync::Mutex::new(()),
+        }
+    }
+
+    /// Renew subscriptions once they expire within this margin. Defaults to 12 hours, which leaves
+    /// many check intervals to retry a failed renewal before the subscription is lost.
+    pub fn renewal_margin(mut self, margin: Duration) -> Self {
+        self.renewal_margin = margin;
+        self
+    }
+
+    /// How often to reconcile subscriptions against Graph. Defaults to 10 minutes.
+    pub fn check_interval(mut self, interval: std::time::Duration) -> Self {
+        self.check_interval = interval;
+        self
+    }
+
+    pub fn watch(&self, spec: SubscriptionSpec) {
+        self.specs.lock().unwrap().insert(spec.key(), spec);
+    }
+
+    /// Stop maintaining a subscription. It's deleted from Graph on the next sync.
+    pub fn unwatch(&self, key: &str) {
+        self.specs.lock().unwrap().remove(key);
+    }
+
+    /// Spawn the background task onto the current tokio runtime. The first sync runs immediately.
+    /// The task keeps running if the event receiver is dropped.
+    pub fn start(self) -> (SubscriptionManagerHandle, UnboundedReceiver<SubscriptionEvent>) {
+        let manager = Arc::new(self);
+        let (tx, rx) = unbounded_channel();
+        let task = tokio::spawn({
+            let manager = manager.clone();
+            async move {
+                loop {
+                    for event in manager.sync().await {
+                        let _ = tx.send(event);
+                    }
+                    tokio::select! {
+                        _ = tokio::time::sleep(manager.check_interval) => {}
+                        _ = manager.wake.notified() => {}
+                    }
+                }
+            }
+        });
+        (SubscriptionManagerHandle { manager, task }, rx)
+    }
+
+    /// Run a single reconciliation pass. Use this directly if you'd rather drive the manager from your own scheduler.
+    pub async fn sync(&self) -> Vec<SubscriptionEvent> {
+        let _reconciling = self.reconciling.lock().await;
+        let mut events = Vec::new();
+        let live = match self.live_subscriptions().await {
+            Ok(live) => Some(live),
+            Err(e) => {
+                events.push(SubscriptionEvent::Failed {
+                    key: None,
+                    error: e.into(),
+                });
+                None
+            }
+        };
+        let specs: Vec<SubscriptionSpec> = self.specs.lock().unwrap().values().cloned().collect();
+        for spec in &specs {
+            match self.sync_one(spec, live.as_deref()).await {
+                Ok(Some(event)) => events.push(event),
+                Ok(None) => {}
+                Err(error) => events.push(SubscriptionEvent::Failed {
+                    key: Some(spec.key()),
+                    error,
+                }),
+            }
+        }
+        let keys = match self.store.keys().await {
+            Ok(keys) => keys,
+            Err(e) => {
+                events.push(SubscriptionEvent::Failed {
+                    key: None,
+                    error: SubscriptionManagerError::Store(e),
+                });
+                return events;
+            }
+        };
+        for key in keys {
+            if specs.iter().any(|s| s.key() == key) {
+                continue;
+            }
+            match self.remove(&key).await {
+                Ok(Some(event)) => events.push(event),
+                Ok(None) => {}
+                Err(error) => events.push(SubscriptionEvent::Failed { key: Some(key), error }),
+            }
+        }
+        events
+    }
+
+    /// Apply the recommended recovery for a lifecycle notification:
+    /// - `reauthorizationRequired`: reauthorize the subscription, falling back to a renewal. A failed
+    ///   reauthorization is reported as [`SubscriptionEvent::Failed`] ahead of the renewal's event.
+    /// - `subscriptionRemoved`: recreate the subscription, and report [`SubscriptionEvent::Missed`] as changes
+    ///   may have happened while it was gone.
+    /// - `missed`: report [`SubscriptionEvent::Missed`], so the caller can catch up from its checkpoint.
+    ///
+    /// Verify the notification's `clientState` before calling this.
+    pub async fn handle_lifecycle(&self, notification: &LifecycleNotification) -> Vec<SubscriptionEvent> {
+        let _reconciling = self.reconciling.lock().await;
+        match self.handle_lifecycle_inner(notification).await {
+            Ok(events) => events,
+            Err((key, error)) => vec![SubscriptionEvent::Failed { key, error }],
+        }
+    }
+
+    async fn handle_lifecycle_inner(
+        &self, notification: &LifecycleNotification,
+    ) -> Result<Vec<SubscriptionEvent>, (Option<String>, SubscriptionManagerError)> {
+        let subscription_id = notification.subscription_id.clone();
+        let key = self.find_key(&subscription_id).await.map_err(|e| (None, e))?;
+        let spec = self.specs.lock().unwrap().get(&key).cloned();
+        let Some(spec) = spec else {
+            return Err((Some(key), SubscriptionManagerError::UnknownSubscription(subscription_id)));
+        };
+        let missed = |subscription_id: String| SubscriptionEvent::Missed {
+            key: key.clone(),
+            resource: spec.resource.clone(),
+            subscription_id,
+        };
+        match notification.lifecycle_event {
+            LifecycleEvent::ReauthorizationRequired => {
+                let error = match self.client.reauthorize_subscription(&subscription_id).await {
+                    Ok(()) => {
+                        return Ok(vec![SubscriptionEvent::Reauthorized {
+                            key: key.clone(),
+                            subscription_id,
+                        }])
+                    }
+                    Err(e) => SubscriptionEvent::Failed {
+                        key: Some(key.clone()),
+                        error: e.into(),
+                    },
+                };
+                // renewing also reauthorizes, and recreates the subscription if it's already gone
+                let event = self.renew(&spec, subscription_id).await.map_err(|e| (Some(key.clone()), e))?;
+                Ok(vec![error, event])
+            }
+            LifecycleEvent::SubscriptionRemoved => {
+                let event = self.recreate(&spec, subscription_id.clone()).await.map_err(|e| (Some(key.clone()), e))?;
+                Ok(vec![event, missed(subscription_id)])
+            }
+            LifecycleEvent::Missed => Ok(vec![missed(subscription_id)]),
+        }
+    }
+
+    async fn find_key(&self, subscription_id: &str) -> Result<String, SubscriptionManagerError> {
+        let keys = self.store.keys().await.map_err(SubscriptionManagerError::Store)?;
+        for key in keys {
+            let stored = self.store.load(&key).await.map_err(SubscriptionManagerError::Store)?;
+            if stored.is_some_and(|s| s.id == subscription_id) {
+                return Ok(key);
+            }
+        }
+        Err(SubscriptionManagerError::UnknownSubscription(subscription_id.to_string()))
+    }
+
+    async fn live_subscriptions(&self) -> MicrosoftResult<Vec<Subscription>> {
+        let mut page = self.client.list_subscriptions().await?;
+        let mut subscriptions = std::mem::take(&mut page.value);
+        while let Some(next) = page.next_link.take() {
+            page = self.client.list_subscriptions().next(next).await?;
+            subscriptions.append(&mut page.value);
+        }
+        Ok(subscriptions)
+    }
+
+    async fn sync_one(
+        &self, spec: &SubscriptionSpec, live: Option<&[Subscription]>,
+    ) -> Result<Option<SubscriptionEvent>, SubscriptionManagerError> {
+        let key = spec.key();
+        let stored = self.store.load(&key).await.map_err(SubscriptionManagerError::Store)?;
+        let Some(stored) = stored else {
+            if let Some(existing) = live.and_then(|live| live.iter().find(|s| spec.matches(s))) {
+                let subscription = existing.clone();
+                self.save(&key, &subscription).await?;
+                return Ok(Some(SubscriptionEvent::Adopted { key, subscription }));
+            }
+            let subscription = self.create(spec).await?;
+            self.save(&key, &subscription).await?;
+            return Ok(Some(SubscriptionEvent::Created { key, subscription }));
+        };
+        let lost = live.is_some_and(|live| !live.iter().any(|s| s.id == stored.id));
+        if lost || stored.is_expired() {
+            return self.recreate(spec, stored.id).await.map(Some);
+        }
+        if !stored.needs_renewal(self.renewal_margin) {
+            return Ok(None);
+        }
+        self.renew(spec, stored.id).await.map(Some)
+    }
+
+    async fn renew(&self, spec: &SubscriptionSpec, id: String) -> Result<SubscriptionEvent, SubscriptionManagerError> {
+        let key = spec.key();
+        let renewed = self.client.renew_subscription(&id).expiration(Utc::now() + spec.lifetime).await;
+        match renewed {
+            Ok(subscription) => {
+                self.save(&key, &subscription).await?;
+                Ok(SubscriptionEvent::Renewed { key, subscription })
+            }
+            Err(MicrosoftError::NotFound(_)) => self.recreate(spec, id).await,
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn create(&self, spec: &SubscriptionSpec) -> MicrosoftResult<Subscription> {
+        let mut r = self
+            .client
+            .create_subscription(spec.resource.clone(), &spec.notification_url)
+            .change_types(spec.change_types.clone())
+            .expiration(Utc::now() + spec.lifetime);
+        if let Some(client_state) = &spec.client_state {
+            r = r.client_state(client_state);
+        }
+        if let Some(url) = &spec.lifecycle_notification_url {
+            r = r.lifecycle_notification_url(url);
+        }
+        if !spec.select.is_empty() {
+            r = r.select(&spec.select.iter().map(String::as_str).collect::<Vec<_>>());
+        }
+        if let Some(certificate) = &spec.encryption_certificate {
+            r = r.include_resource_data(certificate);
+        }
+        r.await
+    }
+
+    async fn recreate(&self, spec: &SubscriptionSpec, previous_id: String) -> Result<SubscriptionEvent, SubscriptionManagerError> {
+        let key = spec.key();
+        let subscription = self.create(spec).await?;
+        self.save(&key, &subscription).await?;
+        Ok(SubscriptionEvent::Recreated {
+            key,
+            previous_id,
+            subscription,
+        })
+    }
+
+    async fn remove(&self, key: &str) -> Result<Option<SubscriptionEvent>, SubscriptionManagerError> {
+        let Some(stored) = self.store.load(key).await.map_err(SubscriptionManagerError::Store)? else {
+            return Ok(None);
+        };
+        match self.client.delete_subscription(&stored.id).await {
+            Ok(()) => {}
+            // already gone, which is what we want
+            Err(MicrosoftError::NotFound(_)) => {}
+            Err(e) => return Err(e.into()),
+        }
+        self.store.remove(key).await.map_err(SubscriptionManagerError::Store)?;
+        Ok(Some(SubscriptionEvent::Removed {
+            key: key.to_string(),
+            subscription_id: stored.id,
+        }))
+    }
+
+    async fn save(&self, key: &str, subscription: &Subscription) -> Result<(), SubscriptionManagerError> {
+        self.store.save(key, subscription).await.map_err(SubscriptionManagerError::Store)
+    }
+}
+
+/// Controls a running [`SubscriptionManager`]. Dropping the handle does not stop the background task; call [`Self::stop`].
+pub struct SubscriptionManagerHandle {
+    manager: Arc<SubscriptionManager>,
+    task: JoinHandle<()>,
+}
+
+impl SubscriptionManagerHandle {
+    /// Start maintaining another subscription. It's created right away rather than on the next interval.
+    pub fn watch(&self, spec: SubscriptionSpec) {
+        self.manager.watch(spec);
+        self.manager.wake.notify_one();
+    }
+
+    pub fn unwatch(&self, key: &str) {
+        self.manager.unwatch(key);
+        self.manager.wake.notify_one();
+    }
+
+    /// Run a sync now instead of waiting for the check interval.
+    pub fn sync_now(&self) {
+        self.manager.wake.notify_one();
+    }
+
+    /// See [`SubscriptionManager::handle_lifecycle`].
+    pub async fn handle_lifecycle(&self, notification: &LifecycleNotification) -> Vec<SubscriptionEvent> {
+        self.manager.handle_lifecycle(notification).await
+    }
+
+    pub fn stop(self) {
+        self.task.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::StaticToken;
+    use crate::test_util::serve;
+    use crate::MicrosoftAuth;
+    use serde_json::{json, Value};
+
+    const RESOURCE: &str = "Users/a@b.com/mailFolders('Inbox')/messages";
+    const NOTIFY: &str = "https://example.com/notify";
+
+    fn subscription(id: &str, expires_in: Duration) -> Value {
+        json!({
+            "id": id,
+            "resource": RESOURCE,
+            "changeType": "created",
+            "notificationUrl": NOTIFY,
+            "expirationDateTime": Utc::now() + expires_in,
+        })
+    }
+
+    #[derive(Default)]
+    struct Graph {
+        live: Vec<Value>,
+        created: usize,
+        /// The subscription was deleted after it was listed.
+        renew_not_found: bool,
+        reauthorize_forbidden: bool,
+        requests: Vec<String>,
+    }
+
+    /// Serves the subscription endpoints from `graph`, returning a manager watching the inbox of a@b.com.
+    async fn manager(graph: Arc<Mutex<Graph>>) -> SubscriptionManager {
+        let url = serve(move |req| {
+            let graph = graph.clone();
+            async move {
+                if req.method() == "POST" {
+                    // slow creates down, so concurrent callers overlap
+                    tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+                }
+                let mut graph = graph.lock().unwrap();
+                let path = req.uri().path().to_string();
+                graph.requests.push(format!("{} {path}", req.method()));
+                let id = path.strip_prefix("/subscriptions/").unwrap_or_default().to_string();
+                match (req.method().as_str(), id.as_str()) {
+                    ("GET", "") => (200, json!({"@odata.context": "", "value": graph.live}).to_string()),
+                    ("POST", "") => {
+                        graph.created += 1;
+                        let subscription = subscription(&format!("new-{}", graph.created), Duration::days(7));
+                        graph.live.push(subscription.clone());
+                        (201, subscription.to_string())
+                    }
+                    ("POST", id) if id.ends_with("/reauthorize") && graph.reauthorize_forbidden => {
+                        (403, r#"{"error":{"code":"ExtensionError","message":"Operation: Reauthorize; Exception: [Status Code: Forbidden]"}}"#.to_string())
+                    }
+                    ("POST", id) if id.ends_with("/reauthorize") => (200, "{}".to_string()),
+                    ("PATCH", _) if !graph.renew_not_found => (200, subscription(&id, Duration::days(7)).to_string()),
+                    _ => (404, r#"{"error":{"code":"ResourceNotFound","message":"Subscription not found."}}"#.to_string()),
+                }
+            }
+        })
+        .await;
+        let client = MicrosoftClient::builder()
+            .http_client(httpclient::Client::new())
+            .base_url(&url)
+            .auth(MicrosoftAuth::provider(StaticToken::new("token")))
+            .build()
+            .unwrap();
+        let manager = SubscriptionManager::new(Arc::new(client), Arc::new(MemorySubscriptionStore::new()));
+        manager.watch(spec());
+        manager
+    }
+
+    fn spec() -> SubscriptionSpec {
+        SubscriptionSpec::new(SubscriptionResource::inbox(Some("a@b.com".to_string())), NOTIFY)
+    }
+
+    #[tokio::test]
+    async fn test_sync() {
+        let graph = Arc::new(Mutex::new(Graph::default()));
+        let manager = manager(graph.clone()).await;
+        let key = spec().key();
+
+        let events = manager.sync().await;
+        assert!(matches!(&events[..], [SubscriptionEvent::Created { subscription, .. }] if subscription.id == "new-1"));
+        // far from expiry, so nothing to do
+        assert!(manager.sync().await.is_empty());
+
+        let expiring = serde_json::from_value(subscription("new-1", Duration::hours(1))).unwrap();
+        manager.store.save(&key, &expiring).await.unwrap();
+        let events = manager.sync().await;
+        let [SubscriptionEvent::Renewed { subscription, .. }] = &events[..] else {
+            panic!("expected a renewal: {events:?}");
+        };
+        assert_eq!(subscription.id, "new-1");
+        assert!(!subscription.needs_renewal(Duration::hours(12)));
+        let stored = manager.store.load(&key).await.unwrap().unwrap();
+        assert_eq!(stored.expiration_date_time, subscription.expiration_date_time);
+        assert_eq!(graph.lock().unwrap().requests.last().unwrap(), "PATCH /subscriptions/new-1");
+
+        manager.store.save(&key, &expiring).await.unwrap();
+        graph.lock().unwrap().renew_not_found = true;
+        let events = manager.sync().await;
+        assert!(matches!(&events[..], [SubscriptionEvent::Recreated { previous_id, subscription, .. }]
+            if previous_id == "new-1" && subscription.id == "new-2"));
+        assert_eq!(manager.store.load(&key).await.unwrap().unwrap().id, "new-2");
+
+        // gone from Graph without a 404 from a renewal
+        graph.lock().unwrap().live.clear();
+        let events = manager.sync().await;
+        assert!(matches!(&events[..], [SubscriptionEvent::Recreated { previous_id, subscription, .. }]
+            if previous_id == "new-2" && subscription.id == "new-3"));
+
+        manager.unwatch(&key);
+        let events = manager.sync().await;
+        assert!(matches!(&events[..], [SubscriptionEvent::Removed { subscription_id, .. }] if subscription_id == "new-3"));
+        assert!(manager.store.keys().await.unwrap().is_empty());
+        assert_eq!(graph.lock().unwrap().requests.last().unwrap(), "DELETE /subscriptions/new-3");
+    }
+
+    #[tokio::test]
+    async fn test_sync_adopts_existing() {
+        let graph = Arc::new(Mutex::new(Graph::default()));
+        graph.lock().unwrap().live.push(subscription("existing", Duration::days(2)));
+        let manager = manager(graph.clone()).await;
+        let events = manager.sync().await;
+        assert!(matches!(&events[..], [SubscriptionEvent::Adopted { subscription, .. }] if subscription.id == "existing"));
+        assert_eq!(graph.lock().unwrap().created, 0);
+    }
+
+    #[test]
+    fn test_spec_matches() {
+        let spec = SubscriptionSpec::new(SubscriptionResource::inbox(Some("a@b.com".to_string())), "https://example.com/notify");
+        let s = r#"{"id":"7f105c7d","resource":"Users/a@b.com/mailFolders('Inbox')/messages","changeType":"created","notificationUrl":"https://example.com/notify","expirationDateTime":"2016-11-20T18:23:45.9356913Z"}"#;
+        let mut subscription = serde_json::from_str::<Subscription>(s).unwrap();
+        assert!(spec.matches(&subscription));
+        subscription.notification_url = "https://example.com/other".to_string();
+        assert!(!spec.matches(&subscription));
+        subscription.notification_url = "https://example.com/notify".to_string();
+        subscription.change_type = vec![ChangeType::Created, ChangeType::Updated];
+        assert!(!spec.matches(&subscription));
+        assert!(spec.clone().change_types([ChangeType::Updated, ChangeType::Created]).matches(&subscription));
+
+        // the same mailbox watched by two endpoints are two subscriptions
+        let staging = SubscriptionSpec::new(spec.resource.clone(), "https://staging.example.com/notify");
+        assert_ne!(spec.key(), staging.key());
+    }
+
+    #[tokio::test]
+    async fn test_sync_endpoints_of_one_resource() {
+        let graph = Arc::new(Mutex::new(Graph::default()));
+        let manager = manager(graph.clone()).await;
+        manager.watch(SubscriptionSpec::new(spec().resource, "https://staging.example.com/notify"));
+        let events = manager.sync().await;
+        assert!(events.iter().all(|e| matches!(e, SubscriptionEvent::Created { .. })), "{events:?}");
+        assert_eq!(events.len(), 2);
+        // neither is mistaken for the other, and renewed, recreated or removed
+        assert!(manager.sync().await.is_empty());
+        assert_eq!(manager.store.keys().await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_recreate() {
+        let graph = Arc::new(Mutex::new(Graph::default()));
+        let manager = manager(graph.clone()).await;
+        manager.sync().await;
+        // Graph removed the subscription, and says so while a periodic sync notices it's gone
+        graph.lock().unwrap().live.clear();
+        let removed: LifecycleNotification =
+            serde_json::from_value(json!({"lifecycleEvent": "subscriptionRemoved", "subscriptionId": "new-1"})).unwrap();
+        let (synced, handled) = tokio::join!(manager.sync(), manager.handle_lifecycle(&removed));
+        let recreated = synced.iter().chain(&handled).filter(|e| matches!(e, SubscriptionEvent::Recreated { .. })).count();
+        assert_eq!(recreated, 1, "{synced:?} {handled:?}");
+        assert_eq!(graph.lock().unwrap().created, 2);
+        assert_eq!(graph.lock().unwrap().live.len(), 1);
+        assert_eq!(manager.store.load(&spec().key()).await.unwrap().unwrap().id, "new-2");
+    }
+
+    #[tokio::test]
+    async fn test_handle_lifecycle() {
+        let graph = Arc::new(Mutex::new(Graph::default()));
+        let manager = manager(graph.clone()).await;
+        manager.sync().await;
+        let notification = |event: &str, id: &str| -> LifecycleNotification {
+            serde_json::from_value(json!({"lifecycleEvent": event, "subscriptionId": id})).unwrap()
+        };
+
+        let events = manager.handle_lifecycle(&notification("reauthorizationRequired", "new-1")).await;
+        assert!(matches!(&events[..], [SubscriptionEvent::Reauthorized { subscription_id, .. }] if subscription_id == "new-1"));
+        assert_eq!(graph.lock().unwrap().requests.last().unwrap(), "POST /subscriptions/new-1/reauthorize");
+
+        graph.lock().unwrap().reauthorize_forbidden = true;
+        let events = manager.handle_lifecycle(&notification("reauthorizationRequired", "new-1")).await;
+        assert!(matches!(&events[..], [
+            SubscriptionEvent::Failed { error: SubscriptionManagerError::Graph(MicrosoftError::Forbidden(_)), .. },
+            SubscriptionEvent::Renewed { subscription, .. },
+        ] if subscription.id == "new-1"), "{events:?}");
+
+        let events = manager.handle_lifecycle(&notification("subscriptionRemoved", "new-1")).await;
+        assert!(matches!(&events[..], [
+            SubscriptionEvent::Recreated { previous_id, subscription, .. },
+            SubscriptionEvent::Missed { subscription_id, resource, .. },
+        ] if previous_id == "new-1" && subscription.id == "new-2" && subscription_id == "new-1"
+            && *resource == SubscriptionResource::inbox(Some("a@b.com".to_string()))));
+
+        let events = manager.handle_lifecycle(&notification("missed", "new-2")).await;
+        assert!(matches!(&events[..], [SubscriptionEvent::Missed { subscription_id, .. }] if subscription_id == "new-2"));
+        let events = manager.handle_lifecycle(&notification("missed", "new-1")).await;
+        assert!(matches!(&events[..], [SubscriptionEvent::Failed { key: None, error: SubscriptionManagerError::UnknownSubscription(id) }] if id == "new-1"));
+    }
+}
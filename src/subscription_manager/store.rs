@@ -0,0 +1,50 @@
+use crate::model::Subscription;
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+pub type StoreError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Persists the subscriptions a [`super::SubscriptionManager`] maintains, keyed by [`super::SubscriptionSpec::key`].
+/// Implement this on top of your database so a restarted process picks up its existing subscriptions
+/// instead of creating duplicates.
+#[async_trait]
+pub trait SubscriptionStore: Send + Sync {
+    async fn load(&self, key: &str) -> Result<Option<Subscription>, StoreError>;
+    async fn save(&self, key: &str, subscription: &Subscription) -> Result<(), StoreError>;
+    async fn remove(&self, key: &str) -> Result<(), StoreError>;
+    async fn keys(&self) -> Result<Vec<String>, StoreError>;
+}
+
+/// Keeps subscriptions in memory. State is lost when the process exits.
+#[derive(Debug, Default)]
+pub struct MemorySubscriptionStore {
+    subscriptions: Mutex<HashMap<String, Subscription>>,
+}
+
+impl MemorySubscriptionStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl SubscriptionStore for MemorySubscriptionStore {
+    async fn load(&self, key: &str) -> Result<Option<Subscription>, StoreError> {
+        Ok(self.subscriptions.lock().unwrap().get(key).cloned())
+    }
+
+    async fn save(&self, key: &str, subscription: &Subscription) -> Result<(), StoreError> {
+        self.subscriptions.lock().unwrap().insert(key.to_string(), subscription.clone());
+        Ok(())
+    }
+
+    async fn remove(&self, key: &str) -> Result<(), StoreError> {
+        self.subscriptions.lock().unwrap().remove(key);
+        Ok(())
+    }
+
+    async fn keys(&self) -> Result<Vec<String>, StoreError> {
+        Ok(self.subscriptions.lock().unwrap().keys().cloned().collect())
+    }
+}
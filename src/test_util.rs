@@ -0,0 +1,51 @@
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response};
+use hyper_util::rt::TokioIo;
+use std::convert::Infallible;
+use std::future::Future;
+
+/// Serve `handler` on a random local port, returning the base url. `handler` answers with a status and a JSON body.
+pub(crate) async fn serve<F, Fut>(handler: F) -> String
+where
+    F: Fn(Request<Incoming>) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = (u16, String)> + Send + 'static,
+{
+    serve_http(move |req| {
+        let res = handler(req);
+        async move {
+            let (status, body) = res.await;
+            Response::builder()
+                .status(status)
+                .header("Content-Type", "application/json")
+                .body(body)
+                .unwrap()
+        }
+    })
+    .await
+}
+
+/// Like [`serve`], for handlers that need to set headers or a non-JSON body.
+pub(crate) async fn serve_http<F, Fut>(handler: F) -> String
+where
+    F: Fn(Request<Incoming>) -> Fut + Clone + Send + Sync + 'static,
+    Fut: Future<Output = Response<String>> + Send + 'static,
+{
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(async move {
+        loop {
+            let (stream, _) = listener.accept().await.unwrap();
+            let handler = handler.clone();
+            tokio::spawn(async move {
+                let svc = service_fn(move |req| {
+                    let res = handler(req);
+                    async move { Ok::<_, Infallible>(res.await) }
+                });
+                let _ = http1::Builder::new().serve_connection(TokioIo::new(stream), svc).await;
+            });
+        }
+    });
+    format!("http://{addr}")
+}
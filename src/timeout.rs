@@ -0,0 +1,22 @@
+use async_trait::async_trait;
+use httpclient::{InMemoryRequest, Middleware, Next, ProtocolError, ProtocolResult, Response};
+use std::time::Duration;
+
+/// Fail a request that takes longer than `duration`, reported as an `io::ErrorKind::TimedOut` protocol error.
+#[derive(Debug)]
+pub(crate) struct Timeout {
+    pub duration: Duration,
+}
+
+#[async_trait]
+impl Middleware for Timeout {
+    async fn handle(&self, request: InMemoryRequest, next: Next<'_>) -> ProtocolResult<Response> {
+        match tokio::time::timeout(self.duration, next.run(request)).await {
+            Ok(res) => res,
+            Err(_) => {
+                let e = std::io::Error::new(std::io::ErrorKind::TimedOut, format!("no response within {:?}", self.duration));
+                Err(ProtocolError::IoError(e))
+            }
+        }
+    }
+}
@@ -0,0 +1,175 @@
+//! A ready-made endpoint for Graph change notifications. Requires the `webhook` feature.
+//!
+//! [`WebhookService`] is a `tower::Service`, so it can be mounted in axum, hyper, or anything else tower-compatible.
+//! It answers the validation handshake, checks `clientState`, and forwards parsed notifications to [`WebhookEvents`].
+//! Use the same service for both `notificationUrl` and `lifecycleNotificationUrl`.
+use crate::model::{validation_token, LifecycleNotification, Notification};
+use futures::future::BoxFuture;
+use futures::Stream;
+use http::{header, Method, Request, Response, StatusCode};
+use http_body_util::{BodyExt, LengthLimitError, Limited};
+use serde::Deserialize;
+use std::convert::Infallible;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+
+/// The largest request body [`WebhookService`] reads. Graph batches notifications, but a batch stays well below this.
+pub const MAX_BODY_BYTES: usize = 4 * 1024 * 1024;
+
+#[derive(Debug, Clone)]
+pub enum WebhookEvent {
+    Change(Box<Notification>),
+    Lifecycle(LifecycleNotification),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Item {
+    Lifecycle(LifecycleNotification),
+    Change(Box<Notification>),
+}
+
+#[derive(Deserialize)]
+struct Batch {
+    value: Vec<Item>,
+}
+
+#[derive(Debug, Clone)]
+pub struct WebhookService {
+    client_state: Option<Arc<str>>,
+    tx: UnboundedSender<WebhookEvent>,
+}
+
+/// The notifications received by a [`WebhookService`]. Ends once every clone of the service is dropped.
+#[derive(Debug)]
+pub struct WebhookEvents {
+    rx: UnboundedReceiver<WebhookEvent>,
+}
+
+impl Stream for WebhookEvents {
+    type Item = WebhookEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+/// Create a webhook endpoint. If `client_state` is set, notifications that don't carry it are dropped.
+pub fn webhook(client_state: Option<String>) -> (WebhookService, WebhookEvents) {
+    let (tx, rx) = unbounded_channel();
+    let service = WebhookService {
+        client_state: client_state.map(Into::into),
+        tx,
+    };
+    (service, WebhookEvents { rx })
+}
+
+fn response(status: StatusCode, body: String) -> Response<String> {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "text/plain")
+        .body(body)
+        .expect("Failed to build response")
+}
+
+impl WebhookService {
+    /// Handle a request that has already been read into memory. Use this if your server isn't tower-based.
+    pub fn handle(&self, method: &Method, query: Option<&str>, body: &[u8]) -> Response<String> {
+        if let Some(token) = query.and_then(validation_token) {
+            return response(StatusCode::OK, token);
+        }
+        if method != Method::POST {
+            return response(StatusCode::METHOD_NOT_ALLOWED, String::new());
+        }
+        let batch: Batch = match serde_json::from_slice(body) {
+            Ok(batch) => batch,
+            Err(e) => return response(StatusCode::BAD_REQUEST, e.to_string()),
+        };
+        for item in batch.value {
+            let (client_state, event) = match item {
+                Item::Change(n) => (n.client_state.clone(), WebhookEvent::Change(n)),
+                Item::Lifecycle(n) => (n.client_state.clone(), WebhookEvent::Lifecycle(n)),
+            };
+            if let Some(expected) = &self.client_state {
+                if client_state.as_deref() != Some(expected) {
+                    continue;
+                }
+            }
+            // nobody is listening anymore. Still acknowledge, or Graph keeps retrying.
+            let _ = self.tx.send(event);
+        }
+        // Graph expects an answer within 3 seconds, so processing happens on the receiving side of the stream.
+        response(StatusCode::ACCEPTED, String::new())
+    }
+}
+
+impl<B> tower_service::Service<Request<B>> for WebhookService
+where
+    B: http_body::Body + Send + 'static,
+    B::Data: Send,
+    B::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    type Response = Response<String>;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: Request<B>) -> Self::Future {
+        let service = self.clone();
+        Box::pin(async move {
+            let (parts, body) = request.into_parts();
+            let body = match Limited::new(body, MAX_BODY_BYTES).collect().await {
+                Ok(body) => body.to_bytes(),
+                Err(e) if e.is::<LengthLimitError>() => return Ok(response(StatusCode::PAYLOAD_TOO_LARGE, String::new())),
+                Err(_) => return Ok(response(StatusCode::BAD_REQUEST, String::new())),
+            };
+            Ok(service.handle(&parts.method, parts.uri.query(), &body))
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::serve_http;
+    use futures::StreamExt;
+    use httpclient::InMemoryResponseExt;
+    use tower_service::Service;
+
+    #[tokio::test]
+    async fn test_webhook() {
+        let (service, mut events) = webhook(Some("secretClientValue".to_string()));
+        let url = serve_http(move |req| {
+            let res = service.clone().call(req);
+            async move { res.await.unwrap() }
+        })
+        .await;
+        let client = httpclient::Client::new();
+
+        let res = client.post(format!("{url}/notify?validationToken=Validation%3a+Testing")).await.unwrap();
+        assert_eq!(res.text().unwrap(), "Validation: Testing");
+
+        let body = r#"{"value":[
+            {"subscriptionId":"a","subscriptionExpirationDateTime":"2016-03-19T22:11:09.952Z","clientState":"wrong","changeType":"created","resource":"users/x/messages/1"},
+            {"subscriptionId":"a","subscriptionExpirationDateTime":"2016-03-19T22:11:09.952Z","clientState":"secretClientValue","changeType":"created","resource":"users/x/messages/2","resourceData":{"id":"2"}},
+            {"lifecycleEvent":"missed","subscriptionId":"a","clientState":"secretClientValue"}
+        ]}"#;
+        let res = client.post(format!("{url}/notify")).text(body.to_string()).await.unwrap();
+        assert_eq!(res.status(), StatusCode::ACCEPTED);
+        let Some(WebhookEvent::Change(n)) = events.next().await else { panic!("expected change notification") };
+        assert_eq!(n.message_id(), Some("2"));
+        assert!(matches!(events.next().await, Some(WebhookEvent::Lifecycle(_))));
+
+        let err = client.post(format!("{url}/notify")).text("nope".to_string()).await.unwrap_err();
+        assert_eq!(err.status(), Some(StatusCode::BAD_REQUEST));
+
+        let body = format!(r#"{{"value":[],"padding":"{}"}}"#, "x".repeat(MAX_BODY_BYTES));
+        let err = client.post(format!("{url}/notify")).text(body).await.unwrap_err();
+        assert_eq!(err.status(), Some(StatusCode::PAYLOAD_TOO_LARGE));
+    }
+}